@@ -0,0 +1,273 @@
+extern crate glob;
+extern crate toml;
+
+use std::fs;
+use std::path::Path;
+
+use self::glob::Pattern;
+use self::toml::Value;
+
+use crate::config::{config_path, is_ref_pattern, read_config, Config};
+use crate::gitlab::{Error, Gitlab};
+
+/// Scopes that allow reading pipelines and merge requests.
+const READ_SCOPES: &[&str] = &["api", "read_api"];
+
+/// Prints one line per finding and counts the problems.
+#[derive(Default)]
+struct Report {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&self, message: &str) {
+        println!("ok: {:}", message);
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.warnings += 1;
+        println!("warning: {:}", message);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.errors += 1;
+        println!("error: {:}", message);
+    }
+
+    /// Prints the totals and returns the exit code: 1 if there were errors.
+    fn finish(self) -> i32 {
+        println!("{:} error(s), {:} warning(s)", self.errors, self.warnings);
+        if self.errors > 0 {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// `check-config`: checks the config file's keys and values, then that the
+/// token, projects and refs work against `gitlab_url`.  Returns the exit
+/// code.
+pub fn run(explicit: Option<&Path>) -> i32 {
+    let mut report = Report::default();
+    let path = match config_path(explicit) {
+        Ok(path) => path,
+        Err(e) => {
+            report.error(&e);
+            return report.finish();
+        }
+    };
+    println!("checking {:}", path.display());
+    match fs::read_to_string(&path) {
+        Ok(source) => check_keys(&source, &mut report),
+        Err(e) => {
+            report.error(&format!("cannot read {:}: {:}", path.display(), e));
+            return report.finish();
+        }
+    }
+
+    match read_config(Some(&path)) {
+        Ok(config) => check_gitlab(&config, &mut report),
+        Err(e) => report.error(&e.to_string()),
+    }
+    report.finish()
+}
+
+/// A table of the config file and the keys set in it.
+struct Table {
+    /// `""` for the top level, otherwise the name in the header.
+    name: String,
+    line: usize,
+    keys: Vec<(String, usize)>,
+}
+
+/// Splits the config into tables by scanning for headers and `key =` lines.
+/// This is not a TOML parser, but it only has to find line numbers in files
+/// that `toml` reads.
+fn tables(source: &str) -> Vec<Table> {
+    let mut tables = vec![Table {
+        name: String::new(),
+        line: 0,
+        keys: Vec::new(),
+    }];
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let name = line.trim_start_matches('[').split(']').next().unwrap_or("").trim();
+            tables.push(Table {
+                name: name.to_string(),
+                line: i + 1,
+                keys: Vec::new(),
+            });
+        } else if let Some((key, _)) = line.split_once('=') {
+            let key = key.trim();
+            let bare = key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+            if !key.is_empty() && bare {
+                let table = tables.last_mut().expect("top level table");
+                table.keys.push((key.to_string(), i + 1));
+            }
+        }
+    }
+    tables
+}
+
+/// Reports keys that no config field reads, usually typos.  Unknown keys
+/// are whatever is in the file but missing once the file is read into a
+/// `Config` and written back, so there is no list of keys to keep in step.
+/// Files that do not parse are left to `read_config` to report.
+fn check_keys(source: &str, report: &mut Report) {
+    let value: Value = match source.parse() {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    let config: Config = match value.clone().try_into() {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+    let known = Value::try_from(&config).expect("config serializes");
+
+    let tables = tables(source);
+    let mut unknown = Vec::new();
+    unknown_keys(&value, &known, &mut Vec::new(), &mut unknown);
+    let mut unknown: Vec<(Option<usize>, String)> = unknown
+        .iter()
+        .map(|path| {
+            // array indices only tell tables apart, the line already does that
+            let name: Vec<&str> = path.iter().filter(|p| p.parse::<usize>().is_err()).map(String::as_str).collect();
+            (line_of(&tables, path), name.join("."))
+        })
+        .collect();
+    unknown.sort();
+    for (line, key) in unknown {
+        match line {
+            Some(line) => report.error(&format!("line {:}: unknown key {:}", line, key)),
+            None => report.error(&format!("unknown key {:}", key)),
+        }
+    }
+    if config.project.is_empty() {
+        report.warning("no [[project]] tables, nothing will be monitored");
+    }
+}
+
+/// Collects the paths of keys in `value` that `known` lacks.  Arrays, such as
+/// the `[[project]]` tables, are compared element by element.
+fn unknown_keys(value: &Value, known: &Value, path: &mut Vec<String>, found: &mut Vec<Vec<String>>) {
+    match (value, known) {
+        (Value::Table(table), Value::Table(known)) => {
+            for (key, value) in table {
+                path.push(key.clone());
+                match known.get(key) {
+                    Some(known) => unknown_keys(value, known, path, found),
+                    None => found.push(path.clone()),
+                }
+                path.pop();
+            }
+        }
+        (Value::Array(values), Value::Array(known)) => {
+            for (i, (value, known)) in values.iter().zip(known).enumerate() {
+                path.push(i.to_string());
+                unknown_keys(value, known, path, found);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Line of the key at `path`, as a key in its table, as a dotted key such as
+/// `icons.preset` at the top level, or as a table header.
+fn line_of(tables: &[Table], path: &[String]) -> Option<usize> {
+    let (key, parents) = path.split_last()?;
+    let (name, index) = match parents {
+        [] => ("", 0),
+        [name] => (name.as_str(), 0),
+        [name, index] => (name.as_str(), index.parse().ok()?),
+        _ => return None,
+    };
+    let in_table = tables
+        .iter()
+        .filter(|t| t.name == name)
+        .nth(index)
+        .and_then(|t| t.keys.iter().find(|(k, _)| k == key))
+        .map(|(_, line)| *line);
+    let dotted = if name.is_empty() {
+        key.clone()
+    } else {
+        format!("{:}.{:}", name, key)
+    };
+    in_table
+        .or_else(|| tables[0].keys.iter().find(|(k, _)| *k == dotted).map(|(_, line)| *line))
+        .or_else(|| tables.iter().find(|t| t.name == dotted).map(|t| t.line))
+}
+
+fn check_gitlab(config: &Config, report: &mut Report) {
+    let token = config.token_source().expect("token source checked by Config::validate");
+    let gl = Gitlab::new(&config.gitlab_url, token, config.parallelism);
+
+    match gl.current_user() {
+        Ok(user) => report.ok(&format!("token belongs to @{:}", user.username)),
+        Err(e) => {
+            // nothing else can work without a valid token
+            report.error(&e.to_string());
+            return;
+        }
+    }
+    match gl.token_info() {
+        Ok(info) if info.scopes.iter().any(|s| READ_SCOPES.contains(&s.as_str())) => {
+            report.ok(&format!("token {:?} has scopes {:}", info.name, info.scopes.join(", ")));
+            if let Some(expires_at) = info.expires_at {
+                report.ok(&format!("token expires on {:}", expires_at));
+            }
+        }
+        Ok(info) => report.error(&format!(
+            "token {:?} needs one of the scopes {:}, it has {:}",
+            info.name,
+            READ_SCOPES.join(", "),
+            info.scopes.join(", ")
+        )),
+        Err(Error::NotFound { .. }) => {
+            report.warning("cannot check the token's scopes, it is not an access token or GitLab is older than 15.5")
+        }
+        Err(e) => report.error(&e.to_string()),
+    }
+
+    for project in &config.project {
+        match gl.default_branch(&project.name) {
+            Ok(_) => report.ok(&format!("project {:} found", project.name)),
+            Err(e) => {
+                report.error(&format!("project {:}: {:}", project.name, e));
+                continue;
+            }
+        }
+        // `ref` is used as written, only `refs` entries can be wildcards
+        let entries = project
+            .ref_name
+            .iter()
+            .map(|r| (r, false))
+            .chain(project.refs.iter().map(|r| (r, true)));
+        for (entry, from_refs) in entries {
+            if from_refs && is_ref_pattern(entry) {
+                check_pattern(&gl, &project.name, entry, report);
+                continue;
+            }
+            match gl.ref_exists(&project.name, entry) {
+                Ok(true) => report.ok(&format!("project {:}: ref {:} found", project.name, entry)),
+                Ok(false) => report.error(&format!("project {:}: no branch or tag named {:}", project.name, entry)),
+                Err(e) => report.error(&format!("project {:}: {:}", project.name, e)),
+            }
+        }
+    }
+}
+
+/// A pattern matching no branch is not an error, there may just be none yet.
+fn check_pattern(gl: &Gitlab, project: &str, pattern: &str, report: &mut Report) {
+    let glob = Pattern::new(pattern).expect("ref pattern checked by Config::validate");
+    match gl.branch_names(project) {
+        Ok(branches) => match branches.iter().filter(|b| glob.matches(b)).count() {
+            0 => report.warning(&format!("project {:}: no branches match {:}", project, pattern)),
+            n => report.ok(&format!("project {:}: {:} branches match {:}", project, n, pattern)),
+        },
+        Err(e) => report.error(&format!("project {:}: {:}", project, e)),
+    }
+}
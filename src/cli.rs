@@ -0,0 +1,286 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+pub const USAGE: &str = "\
+usage: cl-worktool [options] [command]
+
+commands:
+  run                    show the status in the menu bar or tray (default)
+      --headless         print titles to the terminal instead
+      --waybar           print JSON lines for Waybar, i3blocks or polybar
+      --dashboard        full-screen terminal dashboard
+  status                 refresh once, print every project and exit with
+                         0 when passing, 2 when a pipeline failed, 3 when
+                         one is running or pending, 1 on any other error
+  mrs                    list the merge requests waiting on your review
+  pipelines <project>    print the pipeline of each monitored ref
+  watch                  print every refresh until interrupted
+  check-config           check the config file, token, projects and refs
+  print-default-config   print an example config with every option
+
+options:
+  -c, --config <path>    config file to read
+  -f, --format <format>  text or json
+  -q, --quiet            only print errors to stderr
+  -v, --verbose          also print each request to stderr
+  -h, --help             print this help
+";
+
+/// Exit code for a command line that could not be parsed.
+pub const USAGE_ERROR: i32 = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Run(Frontend),
+    Status,
+    Mrs,
+    /// Takes a `Project::name` or title from the config, or any project path.
+    Pipelines(String),
+    Watch,
+    CheckConfig,
+    PrintDefaultConfig,
+    Help,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Frontend {
+    StatusBar,
+    Terminal,
+    Waybar,
+    Dashboard,
+}
+
+/// How `status`, `mrs`, `pipelines` and `watch` print results.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// How much goes to stderr.  Errors are always printed, except while the
+/// dashboard owns the terminal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Nothing is printed; the latest error is kept for `take_error`.
+    Silent,
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub format: Format,
+    pub verbosity: Verbosity,
+}
+
+/// Parses the arguments after the program name.  Options may come before or
+/// after the command; without a command the status bar is run, so the
+/// `--headless`, `--waybar` and `--dashboard` flags work on their own.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut config = None;
+    let mut format = Format::Text;
+    let mut verbosity = Verbosity::Normal;
+    let mut frontend = None;
+    let mut help = false;
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{:} needs a value", name))
+        };
+        match flag.as_str() {
+            "-c" | "--config" => config = Some(PathBuf::from(value("--config")?)),
+            "-f" | "--format" => {
+                format = match value("--format")?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    other => return Err(format!("unknown format {:?}, expected text or json", other)),
+                }
+            }
+            "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+            "-h" | "--help" => help = true,
+            "--headless" => frontend = Some(Frontend::Terminal),
+            "--waybar" => frontend = Some(Frontend::Waybar),
+            "--dashboard" => frontend = Some(Frontend::Dashboard),
+            _ if flag.starts_with('-') && flag != "-" => return Err(format!("unknown option {:}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let name = positional.next();
+    let command = match name.as_deref() {
+        _ if help => Command::Help,
+        None | Some("run") => Command::Run(frontend.unwrap_or(Frontend::StatusBar)),
+        Some("status") => Command::Status,
+        Some("mrs") => Command::Mrs,
+        Some("pipelines") => match positional.next() {
+            Some(project) => Command::Pipelines(project),
+            None => return Err("pipelines needs a project".to_string()),
+        },
+        Some("watch") => Command::Watch,
+        Some("check-config") => Command::CheckConfig,
+        Some("print-default-config") => Command::PrintDefaultConfig,
+        Some(other) => return Err(format!("unknown command {:?}", other)),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument {:?}", extra));
+    }
+    if frontend.is_some() && !matches!(command, Command::Run(_)) {
+        return Err("--headless, --waybar and --dashboard only apply to run".to_string());
+    }
+
+    Ok(Args {
+        command,
+        config,
+        format,
+        verbosity,
+    })
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Silent,
+        1 => Verbosity::Quiet,
+        2 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Prints `message` to stderr, or keeps it for `take_error` when silent.
+pub fn log_error(message: String) {
+    if verbosity() == Verbosity::Silent {
+        *LAST_ERROR.lock().expect("error log poisoned") = Some(message);
+    } else {
+        eprintln!("{:}", message);
+    }
+}
+
+/// The latest error logged while silent, if any since the last call.
+#[cfg(feature = "dashboard")]
+pub fn take_error() -> Option<String> {
+    LAST_ERROR.lock().expect("error log poisoned").take()
+}
+
+/// `eprintln!` for errors, see `log_error`.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::cli::log_error(format!($($arg)*))
+    };
+}
+
+/// `eprintln!` for progress messages, which `--quiet` hides.
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::cli::verbosity() >= $crate::cli::Verbosity::Normal {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// `eprintln!` for detail only wanted with `--verbose`.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::cli::verbosity() >= $crate::cli::Verbosity::Verbose {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Args, String> {
+        parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn no_command_runs_the_status_bar() {
+        let args = parse_args(&[]).expect("valid");
+        assert_eq!(args.command, Command::Run(Frontend::StatusBar));
+        assert_eq!(args.config, None);
+        assert_eq!(args.format, Format::Text);
+        assert_eq!(args.verbosity, Verbosity::Normal);
+        assert_eq!(parse_args(&["--waybar"]).expect("valid").command, Command::Run(Frontend::Waybar));
+        assert_eq!(parse_args(&["run", "--headless"]).expect("valid").command, Command::Run(Frontend::Terminal));
+    }
+
+    #[test]
+    fn options_go_before_or_after_the_command() {
+        for args in [
+            &["-q", "--config", "a.toml", "status", "-f", "json"][..],
+            &["status", "-f", "json", "-c", "a.toml", "--quiet"],
+            &["--format=json", "status", "--config=a.toml", "-q"],
+        ] {
+            let parsed = parse_args(args).expect("valid");
+            assert_eq!(parsed.command, Command::Status, "{:?}", args);
+            assert_eq!(parsed.config, Some(PathBuf::from("a.toml")), "{:?}", args);
+            assert_eq!(parsed.format, Format::Json, "{:?}", args);
+            assert_eq!(parsed.verbosity, Verbosity::Quiet, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn config_value_may_contain_equals() {
+        let args = parse_args(&["--config=dir=x/a.toml", "mrs"]).expect("valid");
+        assert_eq!(args.config, Some(PathBuf::from("dir=x/a.toml")));
+        assert_eq!(args.command, Command::Mrs);
+    }
+
+    #[test]
+    fn pipelines_takes_a_project() {
+        let args = parse_args(&["pipelines", "group/app", "-v"]).expect("valid");
+        assert_eq!(args.command, Command::Pipelines("group/app".to_string()));
+        assert_eq!(args.verbosity, Verbosity::Verbose);
+        assert_eq!(parse_args(&["pipelines"]).unwrap_err(), "pipelines needs a project");
+        assert_eq!(
+            parse_args(&["pipelines", "a", "b"]).unwrap_err(),
+            "unexpected argument \"b\""
+        );
+    }
+
+    #[test]
+    fn frontends_only_apply_to_run() {
+        assert_eq!(
+            parse_args(&["status", "--waybar"]).unwrap_err(),
+            "--headless, --waybar and --dashboard only apply to run"
+        );
+        assert!(parse_args(&["--dashboard", "watch"]).is_err());
+    }
+
+    #[test]
+    fn bad_arguments_are_rejected() {
+        assert_eq!(parse_args(&["-f", "yaml"]).unwrap_err(), "unknown format \"yaml\", expected text or json");
+        assert_eq!(parse_args(&["--config"]).unwrap_err(), "--config needs a value");
+        assert_eq!(parse_args(&["--nope"]).unwrap_err(), "unknown option --nope");
+        assert_eq!(parse_args(&["nope"]).unwrap_err(), "unknown command \"nope\"");
+    }
+
+    #[test]
+    fn help_wins_over_the_command() {
+        assert_eq!(parse_args(&["status", "-h"]).expect("valid").command, Command::Help);
+        assert_eq!(parse_args(&["--help"]).expect("valid").command, Command::Help);
+    }
+}
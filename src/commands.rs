@@ -0,0 +1,201 @@
+extern crate serde_json;
+
+use std::sync::mpsc::channel;
+use std::thread;
+
+use serde::Serialize;
+
+use crate::cli::Format;
+use crate::config::{Config, Project};
+use crate::gitlab::{PipelineStatus, ReviewRequest};
+use crate::render;
+use crate::status::{fetch, fetch_pipelines, Field, FetchError};
+use crate::worker::{self, Refresh, RefreshRequest};
+
+/// Exit codes of `status` and `pipelines`, see `cli::USAGE`.
+const EXIT_FAILED: i32 = 2;
+const EXIT_RUNNING: i32 = 3;
+const EXIT_UNKNOWN: i32 = 1;
+
+/// `status`: refreshes every project once and prints it.
+pub fn status(config: &Config, format: Format) -> i32 {
+    let gl = worker::client(config);
+    let projects = gl.threads().map(&config.project, |p| fetch(&gl, p));
+    match format {
+        Format::Text => print_lines(&render::tooltip(&projects)),
+        Format::Json => print_json(&projects),
+    }
+    exit_code(
+        projects
+            .iter()
+            .flat_map(|p| p.refs.iter().map(|r| &r.pipeline).chain(Some(&p.pipeline))),
+    )
+}
+
+/// A `ReviewRequest` with the project it belongs to.
+#[derive(Serialize)]
+struct ProjectReviewRequest<'a> {
+    project: &'a str,
+    #[serde(flatten)]
+    request: ReviewRequest,
+}
+
+/// `mrs`: lists the merge requests waiting on a review from us, across every
+/// project.
+pub fn mrs(config: &Config, format: Format) -> i32 {
+    let gl = worker::client(config);
+    let listed = gl.threads().map(&config.project, |p| {
+        gl.snapshot(&p.name)
+            .and_then(|snapshot| gl.review_requests(&snapshot, &p.ignore_users))
+    });
+
+    let mut code = 0;
+    let mut requests = Vec::new();
+    for (p, listed) in config.project.iter().zip(listed) {
+        match listed {
+            Ok(listed) => requests.extend(listed.into_iter().map(|request| ProjectReviewRequest {
+                project: &p.name,
+                request,
+            })),
+            Err(e) => {
+                eprintln!("error: {:}: {:}", p.name, e);
+                code = EXIT_UNKNOWN;
+            }
+        }
+    }
+    match format {
+        Format::Text => {
+            for r in &requests {
+                println!(
+                    "{:}!{:} {:} (@{:}) {:}",
+                    r.project, r.request.iid, r.request.title, r.request.author, r.request.web_url
+                );
+            }
+        }
+        Format::Json => print_json(&requests),
+    }
+    code
+}
+
+/// `pipelines`: the pipeline on every monitored ref of one project.  Projects
+/// missing from the config are looked up with the default settings, which
+/// watch only the default branch.
+pub fn pipelines(config: &Config, project: &str, format: Format) -> i32 {
+    let p = config
+        .project
+        .iter()
+        .find(|p| p.name == project)
+        .or_else(|| config.project.iter().find(|p| p.title() == project))
+        .cloned()
+        .unwrap_or_else(|| Project {
+            title: None,
+            name: project.to_string(),
+            ignore_users: Vec::new(),
+            branch_users: Vec::new(),
+            ref_name: None,
+            refs: Vec::new(),
+            title_template: None,
+        });
+
+    let gl = worker::client(config);
+    let (refs, _, pipeline) = fetch_pipelines(&gl, &p);
+    if refs.is_empty() {
+        if let Err(e) = &pipeline {
+            eprintln!("error: {:}: {:}", p.name, e);
+        }
+        return EXIT_UNKNOWN;
+    }
+    match format {
+        Format::Text => {
+            for r in &refs {
+                match &r.pipeline {
+                    Ok(status) => println!("{:}: {:?}", r.ref_name, status),
+                    Err(e) => println!("{:}: error: {:}", r.ref_name, e),
+                }
+            }
+        }
+        Format::Json => print_json(&refs),
+    }
+    exit_code(refs.iter().map(|r| &r.pipeline))
+}
+
+/// `watch`: refreshes every `interval` and prints each result as it comes,
+/// the title as a line of text or the whole `Refresh` as a line of JSON.
+pub fn watch(config: Config, format: Format) -> i32 {
+    let (tx_query, rx_query) = channel::<RefreshRequest>();
+    let config_path = config.path.clone();
+    thread::spawn(move || worker::watch_config(config_path, tx_query));
+
+    let (tx, rx) = channel::<Refresh>();
+    thread::spawn(move || worker::run(config, rx_query, tx, None));
+    for refresh in rx.iter() {
+        match format {
+            Format::Text => println!("{:}", refresh.title),
+            Format::Json => match serde_json::to_string(&refresh) {
+                Ok(line) => println!("{:}", line),
+                Err(e) => eprintln!("error encoding status: {:}", e),
+            },
+        }
+    }
+    0
+}
+
+/// Failed beats unknown, which beats running or pending.
+fn exit_code<'a, I: Iterator<Item = &'a Field<PipelineStatus>>>(pipelines: I) -> i32 {
+    let pipelines: Vec<Result<&PipelineStatus, &FetchError>> = pipelines.map(Result::as_ref).collect();
+    if pipelines.iter().any(|p| matches!(p, Ok(PipelineStatus::Failed))) {
+        EXIT_FAILED
+    } else if pipelines.iter().any(Result::is_err) {
+        EXIT_UNKNOWN
+    } else if pipelines
+        .iter()
+        .any(|p| matches!(p, Ok(PipelineStatus::Running) | Ok(PipelineStatus::Pending)))
+    {
+        EXIT_RUNNING
+    } else {
+        0
+    }
+}
+
+fn print_lines(text: &str) {
+    if !text.is_empty() {
+        println!("{:}", text);
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{:}", json),
+        Err(e) => eprintln!("error encoding status: {:}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gitlab::ErrorKind;
+
+    fn code(pipelines: Vec<Field<PipelineStatus>>) -> i32 {
+        exit_code(pipelines.iter())
+    }
+
+    fn error() -> Field<PipelineStatus> {
+        Err(FetchError::example(ErrorKind::Network))
+    }
+
+    #[test]
+    fn passing_pipelines_exit_with_zero() {
+        assert_eq!(code(vec![]), 0);
+        assert_eq!(
+            code(vec![Ok(PipelineStatus::Success), Ok(PipelineStatus::Skipped), Ok(PipelineStatus::Manual)]),
+            0
+        );
+    }
+
+    #[test]
+    fn failed_beats_error_beats_running() {
+        assert_eq!(code(vec![Ok(PipelineStatus::Pending)]), EXIT_RUNNING);
+        assert_eq!(code(vec![Ok(PipelineStatus::Running), error()]), EXIT_UNKNOWN);
+        assert_eq!(code(vec![error(), Ok(PipelineStatus::Failed), Ok(PipelineStatus::Running)]), EXIT_FAILED);
+    }
+}
@@ -1,31 +1,276 @@
+extern crate glob;
 extern crate toml;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::env;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
-#[derive(Deserialize, Clone, Debug)]
+use self::glob::Pattern;
+
+use crate::icons::IconSet;
+use crate::paths::config_dir;
+use crate::render::PLACEHOLDERS;
+use crate::template::Template;
+
+/// Environment variable naming the config file, checked after `--config`.
+pub const CONFIG_ENV: &str = "CL_WORKTOOL_CONFIG";
+
+/// Commented example config with every option at its default, printed by
+/// `print-default-config`.
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+/// Everything but a token is optional; see `DEFAULT_CONFIG` for what each
+/// option defaults to.  `check-config` serializes it back to find keys that
+/// no field reads.
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
+    /// Shown when no project has anything to report.
+    #[serde(default = "default_title")]
     pub title: String,
+    #[serde(default = "default_gitlab_url")]
     pub gitlab_url: String,
-    pub token: String,
+    /// The access token itself.  Exactly one of `token`, `token_env`,
+    /// `token_file` and `token_command` must be set.
+    pub token: Option<String>,
+    /// Environment variable holding the token.
+    pub token_env: Option<String>,
+    /// File holding the token; a leading `~/` is the home directory.
+    pub token_file: Option<String>,
+    /// Shell command printing the token, such as a password manager CLI.
+    pub token_command: Option<String>,
+    /// Layout of each project in the title, see `render::DEFAULT_TEMPLATE`.
+    pub title_template: Option<String>,
+    #[serde(default)]
+    pub icons: Icons,
+    /// Most GitLab requests to have in flight, and threads fetching, at once.
+    #[serde(default = "default_parallelism")]
+    pub parallelism: usize,
+    /// Seconds between refreshes.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default)]
     pub project: Vec<Project>,
+    /// File this config was read from, so it can be read again.
+    #[serde(skip)]
+    pub path: PathBuf,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+fn default_title() -> String {
+    "GitLab".to_string()
+}
+
+fn default_gitlab_url() -> String {
+    "https://gitlab.com".to_string()
+}
+
+fn default_parallelism() -> usize {
+    8
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+/// The `[icons]` table.  Unset entries come from `preset`, see `icons::IconSet`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Icons {
+    pub preset: Option<String>,
+    pub running: Option<String>,
+    pub pending: Option<String>,
+    pub success: Option<String>,
+    pub failed: Option<String>,
+    pub canceled: Option<String>,
+    pub skipped: Option<String>,
+    pub manual: Option<String>,
+    /// Shown when the pipeline status could not be fetched.
+    pub unknown: Option<String>,
+    /// Shown when a review count or branch list could not be fetched, and
+    /// after the title when GitLab rejects the token.
+    pub error: Option<String>,
+    /// Shown after the title when GitLab cannot be reached.
+    pub offline: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Project {
-    pub title: String,
+    /// Label in the title and menus, the `name` if unset.
+    pub title: Option<String>,
+    /// Path of the project on GitLab, such as `group/project`.  Required.
     pub name: String,
+    /// Merge requests by these users, and notes from them, do not count as
+    /// waiting for review.
+    #[serde(default)]
     pub ignore_users: Vec<String>,
+    /// Names, emails or message mentions marking a merge request's branch as
+    /// ours, to report when its pipeline fails.
+    #[serde(default)]
     pub branch_users: Vec<String>,
+    /// Branch whose pipeline is shown.  Defaults to the project's default
+    /// branch as reported by GitLab.
+    #[serde(rename = "ref")]
+    pub ref_name: Option<String>,
+    /// More refs to watch alongside `ref`.  Entries may be wildcards such as
+    /// `release/*`, matched against the project's branches.
+    #[serde(default)]
+    pub refs: Vec<String>,
+    /// Overrides `Config::title_template` for this project.
+    pub title_template: Option<String>,
+}
+
+impl Project {
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.name)
+    }
 }
 
-pub fn read_config() -> Result<Config, Box<dyn Error>> {
-    let path = Path::new("config.toml");
-    let mut file = File::open(path)?;
-    let mut config = String::new();
-    file.read_to_string(&mut config)?;
-    Ok(toml::from_str(config.as_str())?)
+/// Whether an entry of `Project::refs` is a wildcard rather than a ref name.
+pub fn is_ref_pattern(entry: &str) -> bool {
+    entry.contains(['*', '?', '['])
+}
+
+/// `explicit`, usually the `--config` flag, or else the first of
+/// `find_config`'s candidates that exists.
+pub fn config_path(explicit: Option<&Path>) -> Result<PathBuf, String> {
+    match explicit {
+        Some(path) => Ok(path.to_path_buf()),
+        None => find_config(),
+    }
+}
+
+/// Reads and validates the config at `config_path(explicit)`.
+pub fn read_config(explicit: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+    let path = config_path(explicit)?;
+    let mut file = File::open(&path).map_err(|e| format!("cannot open {:}: {:}", path.display(), e))?;
+    let mut source = String::new();
+    file.read_to_string(&mut source)?;
+    let mut config: Config =
+        toml::from_str(source.as_str()).map_err(|e| format!("error in {:}: {:}", path.display(), e))?;
+    config
+        .validate()
+        .map_err(|e| format!("error in {:}: {:}", path.display(), e))?;
+    config.path = path;
+    Ok(config)
+}
+
+impl Config {
+    /// Checks what deserializing cannot: the token source, icons, title
+    /// templates and ref patterns.
+    pub fn validate(&self) -> Result<(), String> {
+        self.token_source()?;
+        if self.interval == 0 {
+            return Err("interval must be at least 1 second".to_string());
+        }
+        IconSet::from_config(&self.icons).map_err(|e| format!("[icons]: {:}", e))?;
+        let templates = self
+            .project
+            .iter()
+            .filter_map(|p| p.title_template.as_ref())
+            .chain(self.title_template.as_ref());
+        for template in templates {
+            Template::parse(template, PLACEHOLDERS).map_err(|e| format!("title_template {:?}: {:}", template, e))?;
+        }
+        for pattern in self.project.iter().flat_map(|p| &p.refs).filter(|r| is_ref_pattern(r)) {
+            Pattern::new(pattern).map_err(|e| format!("invalid ref pattern {:?}: {:}", pattern, e))?;
+        }
+        Ok(())
+    }
+
+    /// Where to get the token from, see `token`.
+    pub fn token_source(&self) -> Result<TokenSource, String> {
+        let sources: Vec<TokenSource> = vec![
+            self.token.clone().map(TokenSource::Plain),
+            self.token_env.clone().map(TokenSource::Env),
+            self.token_file.clone().map(|f| TokenSource::File(expand_home(&f))),
+            self.token_command.clone().map(TokenSource::Command),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        match sources.len() {
+            1 => Ok(sources.into_iter().next().expect("one token source")),
+            0 => Err("no token, set one of token, token_env, token_file or token_command".to_string()),
+            _ => Err("only one of token, token_env, token_file and token_command may be set".to_string()),
+        }
+    }
+}
+
+/// Where the GitLab access token comes from.  Everything but `Plain` is read
+/// again when GitLab rejects the token, so it can be rotated while running.
+#[derive(Clone, Debug)]
+pub enum TokenSource {
+    Plain(String),
+    Env(String),
+    File(PathBuf),
+    Command(String),
+}
+
+impl TokenSource {
+    pub fn read(&self) -> Result<String, String> {
+        let token = match self {
+            TokenSource::Plain(token) => token.clone(),
+            TokenSource::Env(name) => env::var(name).map_err(|e| format!("token_env {:}: {:}", name, e))?,
+            TokenSource::File(path) => {
+                fs::read_to_string(path).map_err(|e| format!("token_file {:}: {:}", path.display(), e))?
+            }
+            TokenSource::Command(command) => {
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::null())
+                    .output()
+                    .map_err(|e| format!("token_command: {:}", e))?;
+                if !output.status.success() {
+                    return Err(format!(
+                        "token_command {:}: {:}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                String::from_utf8(output.stdout).map_err(|e| format!("token_command: {:}", e))?
+            }
+        };
+        let token = token.trim();
+        if token.is_empty() {
+            return Err("the token is empty".to_string());
+        }
+        Ok(token.to_string())
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// `$CL_WORKTOOL_CONFIG` if set; otherwise `config.toml` in the user's
+/// config directory, then in the current directory.
+fn find_config() -> Result<PathBuf, String> {
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        let path = PathBuf::from(path);
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(format!("{:} is set to {:}, which does not exist", CONFIG_ENV, path.display()))
+        };
+    }
+
+    let candidates: Vec<PathBuf> = config_dir()
+        .map(|dir| dir.join("config.toml"))
+        .into_iter()
+        .chain(Some(PathBuf::from("config.toml")))
+        .collect();
+    candidates.iter().find(|path| path.is_file()).cloned().ok_or_else(|| {
+        let tried: Vec<String> = candidates.iter().map(|p| format!("  {:}", p.display())).collect();
+        format!(
+            "no config file found, pass --config or set {:}; tried:\n{:}",
+            CONFIG_ENV,
+            tried.join("\n")
+        )
+    })
 }
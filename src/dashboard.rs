@@ -0,0 +1,347 @@
+extern crate crossterm;
+extern crate tui;
+
+use std::error::Error;
+use std::io;
+use std::process::Command;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Local;
+
+use self::crossterm::event::{self, Event, KeyCode};
+use self::crossterm::execute;
+use self::crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use self::tui::backend::{Backend, CrosstermBackend};
+use self::tui::layout::{Constraint, Direction, Layout};
+use self::tui::style::{Color, Modifier, Style};
+use self::tui::widgets::{Block, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table, TableState};
+use self::tui::{Frame, Terminal};
+
+use crate::cli::{self, Verbosity};
+use crate::config::Config;
+use crate::gitlab::{ErrorKind, PipelineStatus};
+use crate::render;
+use crate::status::{Field, Problem, ProjectStatus};
+use crate::worker::{self, Refresh, RefreshRequest};
+
+struct ProjectRow {
+    web_url: String,
+    project: ProjectStatus,
+}
+
+/// A line in the project detail view and the page it opens.
+struct Link {
+    label: String,
+    url: String,
+}
+
+impl ProjectRow {
+    fn links(&self) -> Vec<Link> {
+        let mut links: Vec<Link> = self
+            .project
+            .refs
+            .iter()
+            .map(|r| Link {
+                label: match &r.pipeline {
+                    Ok(s) => format!("Pipelines on {:}: {:?}", r.ref_name, s),
+                    Err(e) => format!("Pipelines on {:}: error: {:}", r.ref_name, e),
+                },
+                url: format!("{:}/-/pipelines?ref={:}", self.web_url, r.ref_name),
+            })
+            .collect();
+        if links.is_empty() {
+            links.push(Link {
+                label: "Pipelines".to_string(),
+                url: format!("{:}/-/pipelines", self.web_url),
+            });
+        }
+        links.push(Link {
+            label: match &self.project.review_count {
+                Ok(n) => format!("Merge requests ({:} to review)", n),
+                Err(e) => format!("Merge requests (error: {:})", e),
+            },
+            url: format!("{:}/-/merge_requests", self.web_url),
+        });
+        match &self.project.failing {
+            Ok(failing) => links.extend(failing.iter().map(|mr| Link {
+                label: format!("{:} {:?}", mr.branch, mr.status),
+                url: mr.web_url.clone(),
+            })),
+            Err(e) => links.push(Link {
+                label: format!("Failing branches (error: {:})", e),
+                url: format!("{:}/-/merge_requests", self.web_url),
+            }),
+        }
+        links
+    }
+}
+
+enum View {
+    Projects,
+    Project(usize),
+}
+
+struct App {
+    gitlab_url: String,
+    rows: Vec<ProjectRow>,
+    view: View,
+    projects: TableState,
+    detail: ListState,
+    refreshing: bool,
+    throttled: bool,
+    problems: Vec<Problem>,
+    config_error: Option<String>,
+    /// The latest error logged during the last refresh, see `cli::take_error`.
+    error: Option<String>,
+    message: String,
+}
+
+/// Full-screen dashboard of every configured project.  Refreshes on the same
+/// interval as the status bar, or on demand with `r`.
+pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let (tx_query, rx_query) = channel::<RefreshRequest>();
+    let (tx, rx) = channel::<Refresh>();
+
+    let config_path = config.path.clone();
+    let tx_query_watch = tx_query.clone();
+    thread::spawn(move || worker::watch_config(config_path, tx_query_watch));
+
+    let gitlab_url = config.gitlab_url.trim_end_matches('/').to_string();
+    thread::spawn(move || worker::run(config, rx_query, tx, None));
+
+    // anything printed to stderr would land in the middle of the screen
+    let verbosity = cli::verbosity();
+    cli::set_verbosity(Verbosity::Silent);
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = event_loop(&mut terminal, gitlab_url, &tx_query, &rx);
+    let _ = tx_query.send(RefreshRequest::Shutdown);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    cli::set_verbosity(verbosity);
+    result
+}
+
+fn event_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    gitlab_url: String,
+    tx_query: &Sender<RefreshRequest>,
+    rx: &Receiver<Refresh>,
+) -> Result<(), Box<dyn Error>> {
+    let mut app = App {
+        gitlab_url,
+        rows: Vec::new(),
+        view: View::Projects,
+        projects: TableState::default(),
+        detail: ListState::default(),
+        refreshing: true,
+        throttled: false,
+        problems: Vec::new(),
+        config_error: None,
+        error: None,
+        message: String::new(),
+    };
+
+    loop {
+        while let Ok(refresh) = rx.try_recv() {
+            let gitlab_url = &app.gitlab_url;
+            app.problems = refresh.problems();
+            app.config_error = refresh.config_error.clone();
+            app.error = cli::take_error();
+            app.rows = refresh
+                .projects
+                .into_iter()
+                .map(|project| ProjectRow {
+                    web_url: format!("{:}/{:}", gitlab_url, project.name),
+                    project,
+                })
+                .collect();
+            app.refreshing = false;
+            app.throttled = refresh.throttled;
+            if app.projects.selected().map(|i| i >= app.rows.len()).unwrap_or(true) {
+                app.projects.select(if app.rows.is_empty() { None } else { Some(0) });
+            }
+            if let View::Project(i) = app.view {
+                if i >= app.rows.len() {
+                    app.view = View::Projects;
+                }
+            }
+        }
+
+        terminal.draw(|f| draw(f, &mut app))?;
+
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('q') => return Ok(()),
+                KeyCode::Char('r') => {
+                    app.refreshing = true;
+                    let request = match app.view {
+                        View::Projects => RefreshRequest::Manual,
+                        View::Project(i) => RefreshRequest::Project(app.rows[i].project.name.clone()),
+                    };
+                    tx_query.send(request)?;
+                }
+                KeyCode::Down | KeyCode::Char('j') => select(&mut app, 1),
+                KeyCode::Up | KeyCode::Char('k') => select(&mut app, -1),
+                KeyCode::Enter => {
+                    if let (View::Projects, Some(i)) = (&app.view, app.projects.selected()) {
+                        app.view = View::Project(i);
+                        app.detail.select(Some(0));
+                    }
+                }
+                KeyCode::Esc | KeyCode::Backspace => app.view = View::Projects,
+                KeyCode::Char('o') => {
+                    let url = match app.view {
+                        View::Projects => app.projects.selected().map(|i| app.rows[i].web_url.clone()),
+                        View::Project(i) => app
+                            .detail
+                            .selected()
+                            .and_then(|d| app.rows[i].links().into_iter().nth(d))
+                            .map(|l| l.url),
+                    };
+                    if let Some(url) = url {
+                        app.message = match open_url(&url) {
+                            Ok(()) => format!("opened {:}", url),
+                            Err(e) => format!("error opening {:}: {:}", url, e),
+                        };
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn select(app: &mut App, delta: isize) {
+    let step = |selected: Option<usize>, len: usize| {
+        if len == 0 {
+            return None;
+        }
+        let current = selected.unwrap_or(0) as isize;
+        Some((current + delta).rem_euclid(len as isize) as usize)
+    };
+    match app.view {
+        View::Projects => {
+            let next = step(app.projects.selected(), app.rows.len());
+            app.projects.select(next);
+        }
+        View::Project(i) => {
+            let next = step(app.detail.selected(), app.rows[i].links().len());
+            app.detail.select(next);
+        }
+    }
+}
+
+fn open_url(url: &str) -> io::Result<()> {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    Command::new(opener).arg(url).spawn().map(|_| ())
+}
+
+fn status_style(status: &Field<PipelineStatus>) -> Style {
+    match status {
+        Ok(PipelineStatus::Failed) | Err(_) => Style::default().fg(Color::Red),
+        Ok(PipelineStatus::Running) | Ok(PipelineStatus::Pending) => Style::default().fg(Color::Yellow),
+        Ok(PipelineStatus::Success) => Style::default().fg(Color::Green),
+        Ok(_) => Style::default(),
+    }
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(1)].as_ref())
+        .split(f.size());
+
+    match app.view {
+        View::Projects => {
+            let rows = app.rows.iter().map(|r| {
+                let r = &r.project;
+                let style = if r.is_stale() {
+                    Style::default().add_modifier(Modifier::DIM)
+                } else {
+                    Style::default()
+                };
+                Row::new(vec![
+                    Cell::from(match render::age(r).as_str() {
+                        "" => r.title.clone(),
+                        age => format!("{:} ({:} ago)", r.title, age),
+                    }),
+                    Cell::from(match &r.pipeline {
+                        Ok(s) => format!("{:?}", s),
+                        Err(e) if e.kind == ErrorKind::NotFound => "not found".to_string(),
+                        Err(_) => "error".to_string(),
+                    })
+                    .style(status_style(&r.pipeline)),
+                    Cell::from(match &r.review_count {
+                        Ok(n) => format!("{:}", n),
+                        Err(_) => "error".to_string(),
+                    }),
+                    Cell::from(match &r.failing {
+                        Ok(v) => v.iter().map(|mr| mr.branch.as_str()).collect::<Vec<_>>().join(", "),
+                        Err(_) => "error".to_string(),
+                    })
+                    .style(Style::default().fg(Color::Red)),
+                ])
+                .style(style)
+            });
+            let table = Table::new(rows)
+                .header(
+                    Row::new(vec!["Project", "Pipeline", "To review", "Failing branches"])
+                        .style(Style::default().add_modifier(Modifier::BOLD)),
+                )
+                .block(Block::default().borders(Borders::ALL).title("Projects"))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+                .widths(&[
+                    Constraint::Percentage(25),
+                    Constraint::Percentage(15),
+                    Constraint::Percentage(10),
+                    Constraint::Percentage(50),
+                ]);
+            f.render_stateful_widget(table, chunks[0], &mut app.projects);
+        }
+        View::Project(i) => {
+            let row = &app.rows[i];
+            let items: Vec<ListItem> = row.links().into_iter().map(|l| ListItem::new(l.label)).collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    "{:} ({:} {:})",
+                    row.project.title,
+                    if row.project.is_stale() { "last fetched" } else { "fetched" },
+                    row.project.as_of().with_timezone(&Local).format("%H:%M:%S")
+                )))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            f.render_stateful_widget(list, chunks[0], &mut app.detail);
+        }
+    }
+
+    let help = match app.view {
+        View::Projects => "q quit  r refresh  enter details  o open",
+        View::Project(_) => "q quit  r refresh project  esc back  o open",
+    };
+    let footer = if app.refreshing {
+        format!("{:}  refreshing…", help)
+    } else if !app.message.is_empty() {
+        format!("{:}  {:}", help, app.message)
+    } else if let Some(e) = &app.config_error {
+        format!("{:}  config not reloaded: {:}", help, e)
+    } else if let Some(problem) = app.problems.first() {
+        format!("{:}  {:}", help, problem)
+    } else if let Some(e) = &app.error {
+        format!("{:}  {:}", help, e)
+    } else if app.throttled {
+        format!("{:}  {:}", help, render::THROTTLED)
+    } else {
+        help.to_string()
+    };
+    f.render_widget(Paragraph::new(footer), chunks[1]);
+}
@@ -1,43 +1,67 @@
-extern crate chrono;
 extern crate percent_encoding;
 extern crate reqwest;
 extern crate serde;
+extern crate serde_json;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use chrono::{DateTime, Utc};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
-use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
 
-use reqwest::Error;
+use reqwest::header::{HeaderMap, ETAG, IF_NONE_MATCH, LINK, RETRY_AFTER};
+use reqwest::StatusCode;
 
+use crate::config::TokenSource;
 use crate::gitlab::PipelineStatus::Skipped;
+use crate::parallel::{Semaphore, Threads};
+
+/// Requests are sent at most this many times when GitLab is overloaded.
+const MAX_ATTEMPTS: u32 = 4;
+/// Delay before the first retry, doubled for every further attempt.
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+/// Longest we wait for a rate limit to reset before giving up on a request.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// Shortest time between reads of a token that did not work, so a revoked
+/// token or a failing `token_command` is not retried on every request.
+const TOKEN_REREAD_INTERVAL: Duration = Duration::from_secs(60);
+/// Pipelines of a ref looked at for one that was pushed; refs with only
+/// scheduled pipelines would otherwise have their whole history fetched.
+const MAX_PIPELINES: usize = 100;
+/// Responses kept for conditional requests; beyond this the least recently
+/// used are dropped, so finished pipelines do not pile up forever.
+const MAX_CACHED_RESPONSES: usize = 2000;
 
 #[derive(Deserialize, Debug)]
 struct MergeRequest {
-    id: u32,
     iid: u32,
+    title: String,
     author: MergeRequestAuthor,
     source_branch: String,
     sha: String,
-    updated_at: DateTime<Utc>,
+    web_url: String,
 }
 
 #[derive(Deserialize, Debug)]
 struct MergeRequestAuthor {
-    id: u32,
     username: String,
 }
 
 #[derive(Deserialize, Debug)]
 struct MergeRequestApproval {
-    id: u32,
-    iid: u32,
     approvals_left: u32,
     user_can_approve: bool,
     user_has_approved: bool,
 }
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum PipelineStatus {
     Running,
@@ -49,18 +73,38 @@ pub enum PipelineStatus {
     Manual,
 }
 
+impl PipelineStatus {
+    /// Whether a pipeline with this status can no longer change.
+    pub fn is_finished(self) -> bool {
+        match self {
+            PipelineStatus::Success | PipelineStatus::Failed | PipelineStatus::Canceled | PipelineStatus::Skipped => true,
+            PipelineStatus::Running | PipelineStatus::Pending | PipelineStatus::Manual => false,
+        }
+    }
+
+    /// How much attention a status needs; `Failed` is the highest.
+    pub fn severity(self) -> u8 {
+        match self {
+            PipelineStatus::Failed => 6,
+            PipelineStatus::Running => 5,
+            PipelineStatus::Pending => 4,
+            PipelineStatus::Canceled => 3,
+            PipelineStatus::Manual => 2,
+            PipelineStatus::Skipped => 1,
+            PipelineStatus::Success => 0,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Pipeline {
     id: u32,
     status: PipelineStatus,
-    #[serde(rename = "ref")]
-    ref_name: String,
-    sha: String,
 }
 
 #[derive(Deserialize, Debug)]
 struct PipelineDetail {
-    id: u32,
+    status: PipelineStatus,
     before_sha: String,
 }
 
@@ -72,7 +116,6 @@ struct Commit {
     author_email: String,
     committer_name: String,
     committer_email: String,
-    created_at: DateTime<Utc>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -84,172 +127,855 @@ struct Branch {
 
 #[derive(Deserialize, Debug)]
 struct Note {
-    id: u32,
     author: NoteAuthor,
-    created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
     resolved: Option<bool>,
 }
 
 #[derive(Deserialize, Debug)]
 struct NoteAuthor {
-    id: u32,
     username: String,
 }
 
-pub struct Gitlab<'a> {
+#[derive(Deserialize, Debug)]
+pub struct User {
+    pub username: String,
+}
+
+/// The token used for requests, as described by GitLab.
+#[derive(Deserialize, Debug)]
+pub struct TokenInfo {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProjectDetail {
+    default_branch: Option<String>,
+}
+
+/// Why a request to GitLab failed.  Every variant names the endpoint, the
+/// API path below `gitlab_url`, that was being fetched.
+#[derive(Debug)]
+pub enum Error {
+    /// 401: the token is missing, expired or revoked.
+    Unauthorized { endpoint: String },
+    /// The token could not be read from its `TokenSource`.
+    Token { message: String },
+    /// 404: the resource does not exist or the token cannot see it.
+    NotFound { endpoint: String },
+    /// 429, still after retrying.
+    RateLimited { endpoint: String },
+    /// Any other unsuccessful status.
+    Status { endpoint: String, status: StatusCode },
+    /// GitLab could not be reached, or the connection failed midway.
+    Network { endpoint: String, source: reqwest::Error },
+    /// The response did not have the shape the API documents.
+    Decode { endpoint: String, source: serde_json::Error },
+}
+
+/// What kind of failure an `Error` is, for deciding how to present it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Unauthorized,
+    /// The token could not be read, GitLab was never asked.
+    Token,
+    NotFound,
+    RateLimited,
+    Network,
+    Other,
+}
+
+impl Error {
+    fn from_status(endpoint: String, status: StatusCode) -> Error {
+        match status {
+            StatusCode::UNAUTHORIZED => Error::Unauthorized { endpoint },
+            StatusCode::NOT_FOUND => Error::NotFound { endpoint },
+            StatusCode::TOO_MANY_REQUESTS => Error::RateLimited { endpoint },
+            status => Error::Status { endpoint, status },
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Unauthorized { .. } => ErrorKind::Unauthorized,
+            Error::Token { .. } => ErrorKind::Token,
+            Error::NotFound { .. } => ErrorKind::NotFound,
+            Error::RateLimited { .. } => ErrorKind::RateLimited,
+            Error::Network { .. } => ErrorKind::Network,
+            Error::Status { .. } | Error::Decode { .. } => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Unauthorized { endpoint } => write!(f, "token rejected (401) by {:}", endpoint),
+            Error::Token { message } => write!(f, "cannot read token: {:}", message),
+            Error::NotFound { endpoint } => write!(f, "not found (404): {:}", endpoint),
+            Error::RateLimited { endpoint } => write!(f, "rate limited (429) on {:}", endpoint),
+            Error::Status { endpoint, status } => write!(f, "{:} from {:}", status, endpoint),
+            Error::Network { endpoint, source } => write!(f, "cannot reach {:}: {:}", endpoint, source),
+            Error::Decode { endpoint, source } => write!(f, "unexpected response from {:}: {:}", endpoint, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network { source, .. } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The token as last read from its `TokenSource`.
+#[derive(Default)]
+struct TokenCache {
+    value: Option<Result<String, String>>,
+    /// When a read last failed or gave back a token GitLab had rejected.
+    failed_at: Option<Instant>,
+}
+
+impl TokenCache {
+    fn may_reread(&self) -> bool {
+        !matches!(self.failed_at, Some(at) if at.elapsed() < TOKEN_REREAD_INTERVAL)
+    }
+
+    fn read(&mut self, source: &TokenSource) -> Result<String, String> {
+        let value = source.read();
+        self.failed_at = if value.is_err() { Some(Instant::now()) } else { None };
+        self.value = Some(value.clone());
+        value
+    }
+}
+
+/// A response body kept for conditional requests.
+#[derive(Clone)]
+struct CachedResponse {
+    etag: Option<String>,
+    body: String,
+    next: Option<String>,
+    /// Served without asking GitLab again: the resource can no longer change.
+    permanent: bool,
+}
+
+/// Responses by URL with the least recently used dropped first.
+#[derive(Default)]
+struct ResponseCache {
+    /// Each response with the value of `uses` when it was last used.
+    entries: HashMap<String, (u64, CachedResponse)>,
+    uses: u64,
+}
+
+impl ResponseCache {
+    fn get(&mut self, url: &str) -> Option<CachedResponse> {
+        self.uses += 1;
+        let uses = self.uses;
+        self.entries.get_mut(url).map(|(used, response)| {
+            *used = uses;
+            response.clone()
+        })
+    }
+
+    fn insert(&mut self, url: String, response: CachedResponse) {
+        self.uses += 1;
+        self.entries.insert(url, (self.uses, response));
+        if self.entries.len() > MAX_CACHED_RESPONSES {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(url, _)| url.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Never revalidates `url` again, caching `response` if it is not yet.
+    fn make_permanent(&mut self, url: String, response: CachedResponse) {
+        match self.entries.get_mut(&url) {
+            Some((_, cached)) => cached.permanent = true,
+            None => self.insert(url, CachedResponse { permanent: true, ..response }),
+        }
+    }
+
+    fn remove(&mut self, url: &str) {
+        self.entries.remove(url);
+    }
+}
+
+pub struct Gitlab {
     client: reqwest::Client,
-    host: &'a str,
-    token: &'a str,
+    host: String,
+    token_source: TokenSource,
+    /// Read from `token_source` on first use and again after a 401.
+    token: Mutex<TokenCache>,
+    /// Upper bound on requests in flight, shared by every thread using the client.
+    parallelism: usize,
+    requests: Semaphore,
+    /// Threads fetching for this client, including the ones calling it.
+    threads: Threads,
+    default_branches: Mutex<HashMap<String, Option<String>>>,
+    /// Responses by URL, revalidated with `If-None-Match` on every use.
+    responses: Mutex<ResponseCache>,
+    /// No requests are sent before this, set by `Retry-After` or when
+    /// `RateLimit-Remaining` runs out.
+    throttled_until: Mutex<Option<Instant>>,
+    throttled: AtomicBool,
+}
+
+/// Open merge requests of one project, listed once per refresh so the review
+/// count and failing branches describe the same point in time.
+pub struct ProjectSnapshot {
+    project: String,
+    merge_requests: Vec<MergeRequest>,
 }
 
+/// An open merge request waiting on a review from us.
+#[derive(Serialize, Clone, Debug)]
+pub struct ReviewRequest {
+    pub iid: u32,
+    pub title: String,
+    pub author: String,
+    pub web_url: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct MergeRequestStatus {
     pub branch: String,
     pub status: PipelineStatus,
+    pub web_url: String,
 }
 
-impl<'a> Gitlab<'a> {
-    pub fn new(host: &'a str, token: &'a str) -> Gitlab<'a> {
+impl Gitlab {
+    pub fn new(host: &str, token_source: TokenSource, parallelism: usize) -> Gitlab {
+        let parallelism = parallelism.max(1);
         Gitlab {
             client: reqwest::Client::new(),
-            host,
-            token,
-        }
-    }
-
-    fn get<T: DeserializeOwned>(&self, path: &String) -> Result<T, Error> {
-        self.client
-            .get(format!("{:}{:}", self.host, path).as_str())
-            .header("Private-Token", self.token)
-            .send()?
-            .json()
-    }
-
-    pub fn merge_request_count(&mut self, project: &str, ignore_authors: &Vec<String>) -> Result<usize, Error> {
-        let project_id = utf8_percent_encode(project, &NON_ALPHANUMERIC).to_owned();
-        let merge_requests: Vec<MergeRequest> = self.get(&format!(
-            "/api/v4/projects/{:}/merge_requests?state=opened&per_page=100",
-            project_id
-        ))?;
-
-        let approvals = merge_requests.iter()
-            .filter(|mr| {
-                if !ignore_authors.contains(&mr.author.username) {
-                    let url = format!("/api/v4/projects/{:}/merge_requests/{:}/notes?sort=desc&order_by=updated_at&per_page=1", project_id, mr.iid);
-                    let notes: Result<Vec<Note>, _> = self.get(&url);
-                    match notes {
-                        Ok(notes) => {
-                            notes
-                                .get(0)
-                                .map(|n| !ignore_authors.contains(&n.author.username) && !n.resolved.unwrap_or(false))
-                                .unwrap_or(true)
-                        },
-                        Err(e) => {
-                            println!("error in notes: {:?}", e);
-                            false
-                        }
-                    }
-                } else {
-                    false
+            host: host.to_string(),
+            token_source,
+            token: Mutex::new(TokenCache::default()),
+            parallelism,
+            requests: Semaphore::new(parallelism),
+            threads: Threads::new(parallelism),
+            default_branches: Mutex::new(HashMap::new()),
+            responses: Mutex::new(ResponseCache::default()),
+            throttled_until: Mutex::new(None),
+            throttled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn threads(&self) -> &Threads {
+        &self.threads
+    }
+
+    /// Whether requests were held back by GitLab's rate limit since the last
+    /// call.
+    pub fn take_throttled(&self) -> bool {
+        self.throttled.swap(false, Ordering::SeqCst)
+    }
+
+    /// The token, read on first use.  A failed read is only tried again
+    /// after `TOKEN_REREAD_INTERVAL`.
+    fn token(&self) -> Result<String, Error> {
+        let mut token = self.token.lock().expect("token poisoned");
+        let value = match &token.value {
+            Some(Ok(value)) => Ok(value.clone()),
+            Some(Err(message)) if !token.may_reread() => Err(message.clone()),
+            _ => token.read(&self.token_source),
+        };
+        value.map_err(|message| Error::Token { message })
+    }
+
+    /// Reads the token again after GitLab rejected `rejected`, returning the
+    /// new one if it differs.  Another thread may already have done so.  The
+    /// token is read at most once per `TOKEN_REREAD_INTERVAL`, otherwise a
+    /// token that stays revoked would be read for every request.
+    fn reload_token(&self, rejected: &str) -> Result<Option<String>, Error> {
+        let mut token = self.token.lock().expect("token poisoned");
+        if let Some(Ok(current)) = token.value.as_ref().filter(|t| t.as_deref() != Ok(rejected)) {
+            return Ok(Some(current.clone()));
+        }
+        if !token.may_reread() {
+            return Ok(None);
+        }
+        match token.read(&self.token_source) {
+            Ok(fresh) if fresh == rejected => {
+                token.failed_at = Some(Instant::now());
+                Ok(None)
+            }
+            Ok(fresh) => {
+                progress!("token rejected, retrying with the token read again");
+                Ok(Some(fresh))
+            }
+            Err(message) => Err(Error::Token { message }),
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let url = format!("{:}{:}", self.host, path);
+        let response = self.request(&url)?;
+        self.decode(&url, &response.body)
+    }
+
+    /// Like `get`, but once `is_final` holds for the response it is never
+    /// requested again.
+    fn get_immutable<T, F>(&self, path: &str, is_final: F) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+        F: Fn(&T) -> bool,
+    {
+        let url = format!("{:}{:}", self.host, path);
+        let response = self.request(&url)?;
+        let value = self.decode(&url, &response.body)?;
+        if !response.permanent && is_final(&value) {
+            let mut responses = self.responses.lock().expect("cache poisoned");
+            responses.make_permanent(url, response);
+        }
+        Ok(value)
+    }
+
+    /// `url` without the host, as reported in errors.
+    fn endpoint(&self, url: &str) -> String {
+        url.strip_prefix(self.host.as_str()).unwrap_or(url).to_string()
+    }
+
+    fn decode<T: DeserializeOwned>(&self, url: &str, body: &str) -> Result<T, Error> {
+        serde_json::from_str(body).map_err(|source| Error::Decode {
+            endpoint: self.endpoint(url),
+            source,
+        })
+    }
+
+    /// Fetches `url`, answering from the cache when GitLab replies
+    /// `304 Not Modified` or the cached response is permanent.
+    fn request(&self, url: &str) -> Result<CachedResponse, Error> {
+        let cached = self.responses.lock().expect("cache poisoned").get(url);
+        if let Some(cached) = &cached {
+            if cached.permanent {
+                return Ok(cached.clone());
+            }
+        }
+
+        let mut token = self.token()?;
+        let mut token_reloaded = false;
+        let mut attempt = 0;
+        let mut response = loop {
+            self.wait_for_rate_limit();
+            let result = {
+                let _permit = self.requests.acquire();
+                let mut request = self.client.get(url).header("Private-Token", token.as_str());
+                if let Some(etag) = cached.as_ref().and_then(|c| c.etag.as_ref()) {
+                    request = request.header(IF_NONE_MATCH, etag.as_str());
                 }
-            })
-            .map(|mr| self.get::<MergeRequestApproval>(&format!("/api/v4/projects/{:}/merge_requests/{:}/approvals", project_id, mr.iid)))
-            .filter_map(|mra| {
-                match mra {
-                    Ok(mra) => Some(mra),
-                    Err(e) => {
-                        println!("error in approval: {:?}", e);
-                        None
-                    }
+                request.send()
+            };
+            attempt += 1;
+
+            let unauthorized = matches!(&result, Ok(r) if r.status() == StatusCode::UNAUTHORIZED);
+            if unauthorized && !token_reloaded {
+                token_reloaded = true;
+                if let Some(fresh) = self.reload_token(&token)? {
+                    token = fresh;
+                    continue;
                 }
-            })
-            .filter(|mra| mra.approvals_left > 0 && mra.user_can_approve && !mra.user_has_approved)
-            .count();
+            }
+
+            let retry = match &result {
+                Ok(r) if r.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let delay = retry_after(r.headers()).unwrap_or_else(|| backoff(attempt));
+                    self.throttle(delay);
+                    Some(delay)
+                }
+                Ok(r) if r.status().is_server_error() => Some(retry_after(r.headers()).unwrap_or_else(|| backoff(attempt))),
+                Err(e) if e.is_timeout() => Some(backoff(attempt)),
+                _ => None,
+            };
+            match retry {
+                Some(delay) if may_retry(attempt, delay) => {
+                    progress!("retrying {:} in {:?}", url, delay);
+                    thread::sleep(delay);
+                }
+                _ => {
+                    break result.map_err(|source| Error::Network {
+                        endpoint: self.endpoint(url),
+                        source,
+                    })?
+                }
+            }
+        };
+        verbose!("{:} {:}", response.status(), url);
+        self.observe_rate_limit(response.headers());
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                return Ok(cached);
+            }
+        }
+        if !response.status().is_success() {
+            self.responses.lock().expect("cache poisoned").remove(url);
+            return Err(Error::from_status(self.endpoint(url), response.status()));
+        }
 
-        Ok(approvals)
+        let fresh = CachedResponse {
+            etag: response
+                .headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            next: next_page_url(url, response.headers()),
+            body: response.text().map_err(|source| Error::Network {
+                endpoint: self.endpoint(url),
+                source,
+            })?,
+            permanent: false,
+        };
+        let mut responses = self.responses.lock().expect("cache poisoned");
+        if fresh.etag.is_some() {
+            responses.insert(url.to_string(), fresh.clone());
+        } else {
+            responses.remove(url);
+        }
+        Ok(fresh)
     }
 
-    pub fn pipeline_status(&mut self, project: &str, ref_name: &str) -> Result<PipelineStatus, Error> {
-        let project_id = utf8_percent_encode(project, &NON_ALPHANUMERIC).to_owned();
-        let pipelines: Vec<Pipeline> = self.get(&format!(
-            "/api/v4/projects/{:}/pipelines?ref={:}&per_page=100",
-            project_id, ref_name
-        ))?;
+    /// Blocks while a rate limit reported by GitLab is in effect.
+    fn wait_for_rate_limit(&self) {
+        let until = *self.throttled_until.lock().expect("throttle poisoned");
+        if let Some(until) = until {
+            let now = Instant::now();
+            if until > now {
+                self.throttled.store(true, Ordering::SeqCst);
+                thread::sleep((until - now).min(MAX_WAIT));
+            }
+        }
+    }
 
-        let status = pipelines
-            .iter()
-            // exclude scheduled jobs
-            .filter(|p| {
-                let det: Result<PipelineDetail, _> = self.get(&format!(
-                    "/api/v4/projects/{:}/pipelines/{:}",
-                    project_id, p.id
-                ));
-                det.map(|d| !d.before_sha.trim_matches('0').is_empty())
-                    .unwrap_or_else(|e| {
-                        println!("error in pipeline: {:?}", e);
-                        false
-                    })
-            })
-            .next()
-            .map(|p| p.status)
-            .unwrap_or_else(|| {
-                println!("no details found");
-                Skipped
-            });
+    /// Holds back every request for `delay`.
+    fn throttle(&self, delay: Duration) {
+        self.throttled.store(true, Ordering::SeqCst);
+        let until = Instant::now() + delay.min(MAX_WAIT);
+        let mut throttled_until = self.throttled_until.lock().expect("throttle poisoned");
+        if !matches!(*throttled_until, Some(t) if t >= until) {
+            *throttled_until = Some(until);
+        }
+    }
 
-        Ok(status)
+    /// Stops sending requests until `RateLimit-Reset` once
+    /// `RateLimit-Remaining` reaches zero.
+    fn observe_rate_limit(&self, headers: &HeaderMap) {
+        let number = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        };
+        if number("RateLimit-Remaining") != Some(0) {
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let delay = match number("RateLimit-Reset") {
+            Some(reset) if reset > now => Duration::from_secs(reset - now),
+            _ => backoff(1),
+        };
+        progress!("rate limit reached, pausing requests for {:?}", delay);
+        self.throttle(delay);
     }
 
-    pub fn user_merge_requests(&mut self, project: &str, usernames: &Vec<String>) -> Result<Vec<MergeRequestStatus>, Error> {
-        let project_id = utf8_percent_encode(project, &NON_ALPHANUMERIC).to_owned();
-        let merge_requests: Vec<MergeRequest> = self.get(&format!(
-            "/api/v4/projects/{:}/merge_requests?state=opened&per_page=100",
-            project_id
-        ))?;
-        let result = merge_requests
-            .iter()
-            .filter(|mr| {
-                let branch: Result<Branch, _> = self.get(&format!(
-                    "/api/v4/projects/{:}/repository/branches/{:}",
-                    project_id, mr.source_branch
-                ));
-                match branch {
-                    Ok(branch) => {
-                        !branch.merged
-                            && branch.commit.id == mr.sha
-                            && usernames.iter().any(|u| {
-                                branch.commit.author_name == *u
-                                    || branch.commit.author_email == *u
-                                    || branch.commit.committer_name == *u
-                                    || branch.commit.committer_email == *u
-                                    || branch.commit.message.contains(u)
-                            })
-                    }
-                    Err(e) => {
-                        println!("error fetching branch {:?}", e);
-                        false
-                    }
+    /// Fetches a list endpoint, following pagination until `limit` items
+    /// have been collected or there are no more pages.
+    fn get_list<T: DeserializeOwned>(&self, path: &str, limit: Option<usize>) -> Result<Vec<T>, Error> {
+        let mut items = Vec::new();
+        self.get_pages(path, |mut page: Vec<T>| {
+            items.append(&mut page);
+            match limit {
+                Some(limit) if items.len() >= limit => {
+                    items.truncate(limit);
+                    false
                 }
+                _ => true,
+            }
+        })?;
+        Ok(items)
+    }
+
+    /// Calls `f` with each page of a list endpoint in turn, following the
+    /// `Link` header (or `X-Next-Page` without one) while `f` returns true.
+    fn get_pages<T, F>(&self, path: &str, mut f: F) -> Result<(), Error>
+    where
+        T: DeserializeOwned,
+        F: FnMut(Vec<T>) -> bool,
+    {
+        let mut url = format!("{:}{:}", self.host, path);
+        loop {
+            let response = self.request(&url)?;
+            if !f(self.decode(&url, &response.body)?) {
+                return Ok(());
+            }
+            match response.next {
+                Some(next) => url = next,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// The user the token belongs to.
+    pub fn current_user(&self) -> Result<User, Error> {
+        self.get("/api/v4/user")
+    }
+
+    /// Only personal, project and group access tokens can be described, and
+    /// only by GitLab 15.5 or newer; others give `NotFound`.
+    pub fn token_info(&self) -> Result<TokenInfo, Error> {
+        self.get("/api/v4/personal_access_tokens/self")
+    }
+
+    /// Whether `ref_name` is a branch or tag of the project.
+    pub fn ref_exists(&self, project: &str, ref_name: &str) -> Result<bool, Error> {
+        let project_id = utf8_percent_encode(project, NON_ALPHANUMERIC).to_owned();
+        let ref_id = utf8_percent_encode(ref_name, NON_ALPHANUMERIC).to_owned();
+        match self.get::<Branch>(&format!("/api/v4/projects/{:}/repository/branches/{:}", project_id, ref_id)) {
+            Ok(_) => return Ok(true),
+            Err(Error::NotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+        match self.get::<IgnoredAny>(&format!("/api/v4/projects/{:}/repository/tags/{:}", project_id, ref_id)) {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The project's default branch, or `None` for an empty repository.
+    /// Looked up once per project and remembered for the client's lifetime.
+    pub fn default_branch(&self, project: &str) -> Result<Option<String>, Error> {
+        if let Some(branch) = self.default_branches.lock().expect("cache poisoned").get(project) {
+            return Ok(branch.clone());
+        }
+        let project_id = utf8_percent_encode(project, NON_ALPHANUMERIC).to_owned();
+        let detail: ProjectDetail = self.get(&format!("/api/v4/projects/{:}", project_id))?;
+        self.default_branches
+            .lock()
+            .expect("cache poisoned")
+            .insert(project.to_string(), detail.default_branch.clone());
+        Ok(detail.default_branch)
+    }
+
+    pub fn branch_names(&self, project: &str) -> Result<Vec<String>, Error> {
+        let project_id = utf8_percent_encode(project, NON_ALPHANUMERIC).to_owned();
+        let branches: Vec<Branch> = self.get_list(
+            &format!("/api/v4/projects/{:}/repository/branches?per_page=100", project_id),
+            None,
+        )?;
+        Ok(branches.into_iter().map(|b| b.name).collect())
+    }
+
+    /// Lists every open merge request of a project, following pagination.
+    pub fn snapshot(&self, project: &str) -> Result<ProjectSnapshot, Error> {
+        let project_id = utf8_percent_encode(project, NON_ALPHANUMERIC).to_owned();
+        let merge_requests: Vec<MergeRequest> = self.get_list(
+            &format!("/api/v4/projects/{:}/merge_requests?state=opened&per_page=100", project_id),
+            None,
+        )?;
+        Ok(ProjectSnapshot {
+            project: project.to_string(),
+            merge_requests,
+        })
+    }
+
+    pub fn merge_request_count(&self, snapshot: &ProjectSnapshot, ignore_authors: &[String]) -> Result<usize, Error> {
+        self.review_requests(snapshot, ignore_authors).map(|requests| requests.len())
+    }
+
+    /// Merge requests we can approve and have not, where nobody in
+    /// `ignore_authors` opened it or had the last word in its notes.  Fails
+    /// if the notes or approvals of any of them cannot be fetched, rather
+    /// than undercounting.
+    pub fn review_requests(
+        &self,
+        snapshot: &ProjectSnapshot,
+        ignore_authors: &[String],
+    ) -> Result<Vec<ReviewRequest>, Error> {
+        let project_id = utf8_percent_encode(&snapshot.project, NON_ALPHANUMERIC).to_owned();
+        let candidates: Vec<&MergeRequest> = snapshot
+            .merge_requests
+            .iter()
+            .filter(|mr| !ignore_authors.contains(&mr.author.username))
+            .collect();
+        let needs_review = self.threads.map(&candidates, |mr| {
+            let url = format!("/api/v4/projects/{:}/merge_requests/{:}/notes?sort=desc&order_by=updated_at&per_page=1", project_id, mr.iid);
+            let notes: Vec<Note> = self.get_list(&url, Some(1))?;
+            let awaiting = notes
+                .first()
+                .map(|n| !ignore_authors.contains(&n.author.username) && !n.resolved.unwrap_or(false))
+                .unwrap_or(true);
+            if !awaiting {
+                return Ok(false);
+            }
+
+            let mra: MergeRequestApproval =
+                self.get(&format!("/api/v4/projects/{:}/merge_requests/{:}/approvals", project_id, mr.iid))?;
+            Ok(mra.approvals_left > 0 && mra.user_can_approve && !mra.user_has_approved)
+        });
+        let needs_review = needs_review.into_iter().collect::<Result<Vec<bool>, Error>>()?;
+
+        Ok(candidates
+            .into_iter()
+            .zip(needs_review)
+            .filter(|(_, needs_review)| *needs_review)
+            .map(|(mr, _)| ReviewRequest {
+                iid: mr.iid,
+                title: mr.title.clone(),
+                author: mr.author.username.clone(),
+                web_url: mr.web_url.clone(),
             })
-            .filter(|mr| {
-                let pipelines: Result<Vec<Pipeline>, _> = self.get(&format!(
-                    "/api/v4/projects/{:}/pipelines?status=failed&sha={:}",
-                    project_id, mr.sha
-                ));
-                match pipelines {
-                    Ok(pipelines) => !pipelines.is_empty(),
-                    Err(e) => {
-                        println!("error fetching branch {:?}", e);
-                        false
-                    }
+            .collect())
+    }
+
+    pub fn pipeline_status(&self, project: &str, ref_name: &str) -> Result<PipelineStatus, Error> {
+        let project_id = utf8_percent_encode(project, NON_ALPHANUMERIC).to_owned();
+        let path = format!(
+            "/api/v4/projects/{:}/pipelines?ref={:}&per_page=100",
+            project_id,
+            utf8_percent_encode(ref_name, NON_ALPHANUMERIC)
+        );
+
+        // exclude scheduled jobs.  details are fetched a batch at a time,
+        // newest first, stopping at the first batch with a match.  a detail
+        // that cannot be fetched fails the whole status unless a newer
+        // pipeline already matched, since it might have been the one.
+        let pipelines: Vec<Pipeline> = self.get_list(&path, Some(MAX_PIPELINES))?;
+        for batch in pipelines.chunks(self.parallelism) {
+            let pushed = self.threads.map(batch, |p| {
+                let detail: PipelineDetail = self.get_immutable(
+                    &format!("/api/v4/projects/{:}/pipelines/{:}", project_id, p.id),
+                    |d: &PipelineDetail| d.status.is_finished(),
+                )?;
+                Ok(!detail.before_sha.trim_matches('0').is_empty())
+            });
+            for (p, pushed) in batch.iter().zip(pushed) {
+                if pushed? {
+                    return Ok(p.status);
                 }
-            })
-            .map(|mr| MergeRequestStatus {
+            }
+        }
+
+        progress!("no details found");
+        Ok(Skipped)
+    }
+
+    /// Merge requests on branches last committed to by one of `usernames`
+    /// whose pipeline failed.  A branch that is gone is not ours; any other
+    /// failed lookup fails the whole list rather than leaving a branch out.
+    pub fn user_merge_requests(
+        &self,
+        snapshot: &ProjectSnapshot,
+        usernames: &[String],
+    ) -> Result<Vec<MergeRequestStatus>, Error> {
+        let project_id = utf8_percent_encode(&snapshot.project, NON_ALPHANUMERIC).to_owned();
+        let failing = self.threads.map(&snapshot.merge_requests, |mr| {
+            let branch: Branch = match self.get(&format!(
+                "/api/v4/projects/{:}/repository/branches/{:}",
+                project_id,
+                utf8_percent_encode(&mr.source_branch, NON_ALPHANUMERIC)
+            )) {
+                Ok(branch) => branch,
+                Err(Error::NotFound { .. }) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let ours = !branch.merged
+                && branch.commit.id == mr.sha
+                && usernames.iter().any(|u| {
+                    branch.commit.author_name == *u
+                        || branch.commit.author_email == *u
+                        || branch.commit.committer_name == *u
+                        || branch.commit.committer_email == *u
+                        || branch.commit.message.contains(u)
+                });
+            if !ours {
+                return Ok(None);
+            }
+
+            let pipelines: Vec<Pipeline> = self.get_list(
+                &format!("/api/v4/projects/{:}/pipelines?status=failed&sha={:}&per_page=1", project_id, mr.sha),
+                Some(1),
+            )?;
+            if pipelines.is_empty() {
+                return Ok(None);
+            }
+
+            Ok(Some(MergeRequestStatus {
                 branch: mr.source_branch.clone(),
                 status: PipelineStatus::Failed,
-            })
-            .collect();
+                web_url: mr.web_url.clone(),
+            }))
+        });
+        failing.into_iter().filter_map(Result::transpose).collect()
+    }
+}
+
+/// `Retry-After` in seconds; HTTP dates are not used by GitLab.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Whether to try again after `attempt` failed and asked us to wait `delay`.
+fn may_retry(attempt: u32, delay: Duration) -> bool {
+    attempt < MAX_ATTEMPTS && delay <= MAX_WAIT
+}
+
+/// Exponential backoff for the given attempt, plus up to half again as
+/// jitter so clients that failed together do not retry together.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BACKOFF_BASE * 2u32.pow(attempt.saturating_sub(1));
+    // every `RandomState` is seeded differently, which is random enough here
+    let random = RandomState::new().build_hasher().finish();
+    delay + delay / 2 * (random % 1000) as u32 / 1000
+}
+
+/// The next page of a paginated response: the `rel="next"` entry of the
+/// `Link` header, or `url` with its `page` replaced by `X-Next-Page`.
+fn next_page_url(url: &str, headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(LINK).and_then(|v| v.to_str().ok());
+    if let Some(link) = link {
+        return link.split(',').find_map(|entry| {
+            let mut parts = entry.split(';');
+            let target = parts.next()?.trim().trim_start_matches('<').trim_end_matches('>');
+            if parts.any(|p| p.trim() == "rel=\"next\"") {
+                Some(target.to_string())
+            } else {
+                None
+            }
+        });
+    }
 
-        Ok(result)
+    let page = headers
+        .get("X-Next-Page")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())?;
+    let (base, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => (url, ""),
+    };
+    let mut params: Vec<String> = query
+        .split('&')
+        .filter(|p| !p.is_empty() && !p.starts_with("page="))
+        .map(str::to_string)
+        .collect();
+    params.push(format!("page={:}", page));
+    Some(format!("{:}?{:}", base, params.join("&")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn backoff_doubles_with_at_most_half_again_as_jitter() {
+        for attempt in 1..MAX_ATTEMPTS {
+            let base = BACKOFF_BASE * 2u32.pow(attempt - 1);
+            let delays: Vec<Duration> = (0..20).map(|_| backoff(attempt)).collect();
+            for delay in &delays {
+                assert!(*delay >= base && *delay < base + base / 2, "{:?} for attempt {:}", delay, attempt);
+            }
+            assert!(delays.iter().any(|d| *d != delays[0]), "no jitter in {:?}", delays);
+        }
+    }
+
+    #[test]
+    fn retries_stop_at_max_attempts_and_max_wait() {
+        assert!(may_retry(1, backoff(1)));
+        assert!(may_retry(MAX_ATTEMPTS - 1, backoff(MAX_ATTEMPTS - 1)));
+        assert!(!may_retry(MAX_ATTEMPTS, Duration::from_secs(0)));
+        assert!(may_retry(1, MAX_WAIT));
+        assert!(!may_retry(1, MAX_WAIT + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn retry_after_reads_seconds() {
+        assert_eq!(retry_after(&headers(&[("retry-after", "30")])), Some(Duration::from_secs(30)));
+        assert_eq!(retry_after(&headers(&[("retry-after", " 5 ")])), Some(Duration::from_secs(5)));
+        assert_eq!(retry_after(&headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")])), None);
+        assert_eq!(retry_after(&headers(&[])), None);
+    }
+
+    #[test]
+    fn next_page_prefers_link_over_x_next_page() {
+        let url = "https://gitlab/api/v4/projects/1/pipelines?ref=main&page=1";
+        let link = "<https://gitlab/api/v4/x?page=1>; rel=\"first\", <https://gitlab/api/v4/x?page=2>; rel=\"next\"";
+        assert_eq!(
+            next_page_url(url, &headers(&[("link", link), ("x-next-page", "5")])),
+            Some("https://gitlab/api/v4/x?page=2".to_string())
+        );
+        // a Link header without a next page is the last page
+        let last = "<https://gitlab/api/v4/x?page=1>; rel=\"first\"";
+        assert_eq!(next_page_url(url, &headers(&[("link", last), ("x-next-page", "5")])), None);
+    }
+
+    #[test]
+    fn next_page_falls_back_to_x_next_page() {
+        let url = "https://gitlab/api/v4/projects/1/pipelines?ref=main&page=1";
+        assert_eq!(
+            next_page_url(url, &headers(&[("x-next-page", "2")])),
+            Some("https://gitlab/api/v4/projects/1/pipelines?ref=main&page=2".to_string())
+        );
+        assert_eq!(
+            next_page_url("https://gitlab/api/v4/users", &headers(&[("x-next-page", "3")])),
+            Some("https://gitlab/api/v4/users?page=3".to_string())
+        );
+        assert_eq!(next_page_url(url, &headers(&[("x-next-page", "")])), None);
+        assert_eq!(next_page_url(url, &headers(&[])), None);
+    }
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            etag: Some("\"1\"".to_string()),
+            body: body.to_string(),
+            next: None,
+            permanent: false,
+        }
+    }
+
+    #[test]
+    fn cache_drops_least_recently_used() {
+        let mut cache = ResponseCache::default();
+        for i in 0..MAX_CACHED_RESPONSES {
+            cache.insert(i.to_string(), response("old"));
+        }
+        assert!(cache.get("0").is_some());
+        cache.insert("new".to_string(), response("new"));
+        assert_eq!(cache.entries.len(), MAX_CACHED_RESPONSES);
+        assert!(cache.get("0").is_some());
+        assert!(cache.get("1").is_none());
+        assert!(cache.get("new").is_some());
+    }
+
+    #[test]
+    fn permanent_responses_are_bounded_too() {
+        let mut cache = ResponseCache::default();
+        cache.make_permanent("first".to_string(), response("done"));
+        assert!(cache.get("first").map(|r| r.permanent).unwrap_or(false));
+        for i in 0..MAX_CACHED_RESPONSES {
+            cache.make_permanent(i.to_string(), response("done"));
+        }
+        assert!(cache.get("first").is_none());
     }
 }
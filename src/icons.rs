@@ -0,0 +1,79 @@
+use crate::config::Icons;
+use crate::gitlab::PipelineStatus;
+
+/// Text shown for each pipeline status and error state.
+#[derive(Clone, Debug)]
+pub struct IconSet {
+    pub running: String,
+    pub pending: String,
+    pub success: String,
+    pub failed: String,
+    pub canceled: String,
+    pub skipped: String,
+    pub manual: String,
+    pub unknown: String,
+    pub error: String,
+    pub offline: String,
+}
+
+/// Names accepted by `[icons] preset`.
+pub const PRESETS: &[&str] = &["emoji", "ascii", "professional"];
+
+impl IconSet {
+    pub fn preset(name: &str) -> Option<IconSet> {
+        let icons = match name {
+            "emoji" => ["🏃", "🕗", "", "💩", "✋", "⦳", "", "?", "⨳", "🔌"],
+            // renders in any terminal or font
+            "ascii" => [">", "~", "", "!", "x", "-", "", "?", "#", " offline"],
+            // plain symbols that are fine to have on a shared screen
+            "professional" => ["⟳", "…", "", "✗", "⊘", "↷", "", "?", "⚠", " offline"],
+            _ => return None,
+        };
+        let [running, pending, success, failed, canceled, skipped, manual, unknown, error, offline] = icons;
+        Some(IconSet {
+            running: running.to_string(),
+            pending: pending.to_string(),
+            success: success.to_string(),
+            failed: failed.to_string(),
+            canceled: canceled.to_string(),
+            skipped: skipped.to_string(),
+            manual: manual.to_string(),
+            unknown: unknown.to_string(),
+            error: error.to_string(),
+            offline: offline.to_string(),
+        })
+    }
+
+    /// Resolves the `[icons]` table: the named preset (emoji by default) with
+    /// any individually configured entries on top.
+    pub fn from_config(icons: &Icons) -> Result<IconSet, String> {
+        let preset = icons.preset.as_deref().unwrap_or("emoji");
+        let base = IconSet::preset(preset)
+            .ok_or_else(|| format!("unknown icon preset {:?}, expected one of: {:}", preset, PRESETS.join(", ")))?;
+        let pick = |configured: &Option<String>, default: String| configured.clone().unwrap_or(default);
+        Ok(IconSet {
+            running: pick(&icons.running, base.running),
+            pending: pick(&icons.pending, base.pending),
+            success: pick(&icons.success, base.success),
+            failed: pick(&icons.failed, base.failed),
+            canceled: pick(&icons.canceled, base.canceled),
+            skipped: pick(&icons.skipped, base.skipped),
+            manual: pick(&icons.manual, base.manual),
+            unknown: pick(&icons.unknown, base.unknown),
+            error: pick(&icons.error, base.error),
+            offline: pick(&icons.offline, base.offline),
+        })
+    }
+
+    pub fn status(&self, status: PipelineStatus) -> &str {
+        match status {
+            PipelineStatus::Running => &self.running,
+            PipelineStatus::Pending => &self.pending,
+            PipelineStatus::Success => &self.success,
+            PipelineStatus::Failed => &self.failed,
+            PipelineStatus::Canceled => &self.canceled,
+            PipelineStatus::Skipped => &self.skipped,
+            PipelineStatus::Manual => &self.manual,
+        }
+    }
+}
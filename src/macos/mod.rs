@@ -3,15 +3,21 @@ extern crate fruitbasket;
 extern crate objc;
 extern crate objc_foundation;
 
+use std::cell::RefCell;
+use std::ptr;
 use std::sync::mpsc::Sender;
 
 use objc::*;
 
+use crate::render;
+use crate::status::Problem;
+use crate::surface::{StatusSurface, Stopper};
+use crate::worker::{Refresh, RefreshRequest};
 use crate::NSCallback;
 
 use self::cocoa::appkit::NSStatusBar;
 use self::cocoa::appkit::{NSButton, NSMenu, NSMenuItem, NSStatusItem, NSVariableStatusItemLength};
-use self::cocoa::base::{nil, YES};
+use self::cocoa::base::{nil, NO, YES};
 use self::cocoa::foundation::NSString;
 use self::fruitbasket::{FruitApp, FruitStopper};
 use self::rustnsobject::{NSObj, NSObjCallbackTrait, NSObjTrait};
@@ -25,27 +31,54 @@ pub struct OSXStatusBar {
     app: FruitApp,
     status_bar_item: *mut objc::runtime::Object,
     menu_bar: *mut objc::runtime::Object,
+    /// Disabled item at the top of the menu, shown while the config file
+    /// fails to reload.
+    config_error_item: *mut objc::runtime::Object,
+    /// Disabled items below it with a line per problem and per project,
+    /// replaced on every update.
+    status_items: RefCell<Vec<*mut objc::runtime::Object>>,
+}
+
+/// A menu item without an action, which the menu shows disabled.  The caller
+/// owns it.
+unsafe fn disabled_item(title: &str) -> *mut Object {
+    let title = NSString::alloc(nil).init_str(title);
+    let empty = NSString::alloc(nil).init_str("");
+    let item = NSMenuItem::alloc(nil).initWithTitle_action_keyEquivalent_(
+        title,
+        objc::runtime::Sel::from_ptr(ptr::null()),
+        empty,
+    );
+    let _: () = msg_send![title, release];
+    let _: () = msg_send![empty, release];
+    item
 }
 
 impl OSXStatusBar {
-    pub fn new(title: &String, tx: Sender<String>) -> OSXStatusBar {
+    pub fn new(title: &str, tx: Sender<RefreshRequest>) -> OSXStatusBar {
         unsafe {
             let app = FruitApp::new();
             app.set_activation_policy(fruitbasket::ActivationPolicy::Prohibited);
             let status_bar = NSStatusBar::systemStatusBar(nil);
 
+            let config_error_item = disabled_item("");
+            let _: () = msg_send![config_error_item, setHidden: YES];
+
             let mut bar = OSXStatusBar {
                 app,
                 status_bar_item: status_bar.statusItemWithLength_(NSVariableStatusItemLength),
                 menu_bar: NSMenu::new(nil),
+                config_error_item,
+                status_items: RefCell::new(Vec::new()),
                 object: NSObj::alloc(tx),
             };
+            bar.menu_bar.addItem_(config_error_item);
 
             // Default mode for menu bar items: blue highlight when selected
             let _: () = msg_send![bar.status_bar_item, setHighlightMode: YES];
 
             // Set title.  Only displayed if image fails to load.
-            let title = NSString::alloc(nil).init_str(title.as_str());
+            let title = NSString::alloc(nil).init_str(title);
             NSButton::setTitle_(bar.status_bar_item, title);
             let _: () = msg_send![title, release];
 
@@ -59,10 +92,6 @@ impl OSXStatusBar {
         }
     }
 
-    pub fn stopper(&self) -> FruitStopper {
-        self.app.stopper()
-    }
-
     // TODO: whole API should accept menu option.  this whole thing should
     // be split out into its own recursive menu-builder trait.  this is
     // horrible.
@@ -102,8 +131,16 @@ impl OSXStatusBar {
             item
         }
     }
+}
+
+impl Stopper for FruitStopper {
+    fn stop(&self) {
+        FruitStopper::stop(self);
+    }
+}
 
-    pub fn set_title(&self, title: &str) {
+impl StatusSurface for OSXStatusBar {
+    fn set_title(&self, title: &str) {
         unsafe {
             let title = NSString::alloc(nil).init_str(title);
             NSButton::setTitle_(self.status_bar_item, title);
@@ -111,7 +148,49 @@ impl OSXStatusBar {
         }
     }
 
-    pub fn run(&mut self, block: bool) {
+    fn update(&self, refresh: &Refresh) {
+        self.set_title(&refresh.title);
+        unsafe {
+            match &refresh.config_error {
+                Some(e) => {
+                    let title = NSString::alloc(nil).init_str(&format!("Config not reloaded: {:}", e));
+                    let _: () = msg_send![self.config_error_item, setTitle: title];
+                    let _: () = msg_send![title, release];
+                    let _: () = msg_send![self.config_error_item, setHidden: NO];
+                }
+                None => {
+                    let _: () = msg_send![self.config_error_item, setHidden: YES];
+                }
+            }
+
+            let mut status_items = self.status_items.borrow_mut();
+            for item in status_items.drain(..) {
+                let _: () = msg_send![self.menu_bar, removeItem: item];
+            }
+            let problems = refresh.problems().iter().map(Problem::to_string).collect::<Vec<_>>();
+            let lines = problems.into_iter().chain(refresh.projects.iter().map(render::tooltip_line));
+            for line in lines {
+                status_items.push(disabled_item(&line));
+            }
+            if !status_items.is_empty() {
+                let separator = NSMenuItem::separatorItem(nil);
+                let _: () = msg_send![separator, retain];
+                status_items.push(separator);
+            }
+            // right below the config error item; the menu keeps them alive
+            for (i, &item) in status_items.iter().enumerate() {
+                let index = (i + 1) as isize;
+                let _: () = msg_send![self.menu_bar, insertItem: item atIndex: index];
+                let _: () = msg_send![item, release];
+            }
+        }
+    }
+
+    fn add_item(&mut self, item: &str, callback: NSCallback, selected: bool) {
+        let _ = OSXStatusBar::add_item(self, None, item, callback, selected);
+    }
+
+    fn run(&mut self, block: bool) {
         let period = match block {
             true => fruitbasket::RunPeriod::Forever,
             _ => fruitbasket::RunPeriod::Once,
@@ -119,4 +198,8 @@ impl OSXStatusBar {
 
         let _ = self.app.run(period);
     }
+
+    fn stopper(&self) -> Box<dyn Stopper> {
+        Box::new(self.app.stopper())
+    }
 }
@@ -22,13 +22,14 @@ use self::objc_id::WeakId;
 
 use std::sync::mpsc::Sender;
 
+use crate::worker::RefreshRequest;
 use crate::NSCallback;
 
 pub struct RustWrapperClass {
     pub objc: Id<ObjcSubclass, Shared>,
     pub cb_fn: Option<Box<dyn Fn(&mut RustWrapperClass, u64)>>,
     pub map: BTreeMap<u64, NSCallback>,
-    pub tx: Sender<String>,
+    pub tx: Sender<RefreshRequest>,
 }
 
 pub type NSObj = Box<RustWrapperClass>;
@@ -49,7 +50,7 @@ impl NSObjCallbackTrait for RustWrapperClass {
 }
 
 pub trait NSObjTrait {
-    fn alloc(tx: Sender<String>) -> NSObj;
+    fn alloc(tx: Sender<RefreshRequest>) -> NSObj;
     fn selector(&self) -> Sel;
     fn take_objc(&mut self) -> NSObjc;
     fn add_callback(&mut self, v: *const Object, cb: NSCallback);
@@ -60,7 +61,7 @@ impl NSObjTrait for NSObj {
         let sender: u64 = item as u64;
         self.set_value(sender, cb);
     }
-    fn alloc(tx: Sender<String>) -> NSObj {
+    fn alloc(tx: Sender<RefreshRequest>) -> NSObj {
         let objc = ObjcSubclass::new().share();
         let rust = Box::new(RustWrapperClass {
             objc: objc,
@@ -1,142 +1,161 @@
-use std::{thread, time::Duration};
+use std::{env, thread};
 use std::process::exit;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
 
-use crate::config::{Config, read_config};
-use crate::gitlab::{Gitlab, PipelineStatus};
-use crate::macos::OSXStatusBar;
+use crate::cli::{Command, Frontend, USAGE, USAGE_ERROR};
+use crate::config::{read_config, DEFAULT_CONFIG};
+use crate::surface::StatusSurface;
+use crate::worker::{Refresh, RefreshRequest};
 
+#[macro_use]
+mod cli;
+mod check;
+mod commands;
 mod config;
+#[cfg(feature = "dashboard")]
+mod dashboard;
 mod gitlab;
+mod icons;
+#[cfg(feature = "macos")]
 mod macos;
-
-pub type NSCallback = Box<dyn Fn(u64, &Sender<String>)>;
+mod parallel;
+mod paths;
+mod render;
+mod state;
+mod status;
+mod surface;
+mod template;
+mod terminal;
+#[cfg(feature = "tray")]
+mod tray;
+mod waybar;
+mod worker;
+
+pub type NSCallback = Box<dyn Fn(u64, &Sender<RefreshRequest>)>;
 
 fn main() {
-    let config = read_config().expect("error reading config.toml");
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprint!("error: {:}\n\n{:}", e, USAGE);
+            exit(USAGE_ERROR);
+        }
+    };
+    cli::set_verbosity(args.verbosity);
+
+    match args.command {
+        Command::Help => {
+            print!("{:}", USAGE);
+            return;
+        }
+        Command::PrintDefaultConfig => {
+            print!("{:}", DEFAULT_CONFIG);
+            return;
+        }
+        Command::CheckConfig => exit(check::run(args.config.as_deref())),
+        _ => {}
+    }
+
+    let config = match read_config(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {:}", e);
+            exit(1);
+        }
+    };
+
+    let frontend = match args.command {
+        Command::Run(frontend) => frontend,
+        Command::Status => exit(commands::status(&config, args.format)),
+        Command::Mrs => exit(commands::mrs(&config, args.format)),
+        Command::Pipelines(project) => exit(commands::pipelines(&config, &project, args.format)),
+        Command::Watch => exit(commands::watch(config, args.format)),
+        Command::Help | Command::PrintDefaultConfig | Command::CheckConfig => unreachable!("handled above"),
+    };
 
-    let (tx_query, rx_query) = channel::<String>();
+    if frontend == Frontend::Dashboard {
+        #[cfg(feature = "dashboard")]
+        {
+            if let Err(e) = dashboard::run(config) {
+                eprintln!("error: {:}", e);
+                exit(1);
+            }
+            return;
+        }
+        #[cfg(not(feature = "dashboard"))]
+        {
+            eprintln!("error: --dashboard needs a build with the dashboard feature");
+            exit(1);
+        }
+    }
+
+    let (tx_query, rx_query) = channel::<RefreshRequest>();
 
     let mut status_bar = {
-        let mut status_bar = OSXStatusBar::new(&config.title, tx_query.clone());
+        let mut status_bar = new_status_surface(&config.title, tx_query.clone(), frontend);
         {
             let cb: NSCallback = Box::new(move |_sender, tx| {
-                tx.send("manual".to_string()).expect("manual refresh send failed");
+                tx.send(RefreshRequest::Manual).expect("manual refresh send failed");
             });
-            let _ = status_bar.add_item(None, "Refresh", cb, false);
+            status_bar.add_item("Refresh", cb, false);
         }
         {
-            let cb: NSCallback = Box::new(move |_sender, _tx| {
-                exit(0);
+            let cb: NSCallback = Box::new(move |_sender, tx| {
+                tx.send(RefreshRequest::ConfigReloaded).expect("config reload send failed");
             });
-            let _ = status_bar.add_item(None, "Quit", cb, false);
+            status_bar.add_item("Reload Config", cb, false);
+        }
+        {
+            // state is saved after every refresh, a request in flight has
+            // nothing worth waiting for
+            let cb: NSCallback = Box::new(move |_sender, _tx| exit(0));
+            status_bar.add_item("Quit", cb, false);
         }
-
-        let tx_query_manual = tx_query.clone();
-        thread::spawn(move || {
-            loop {
-                tx_query_manual.send("interval".to_string()).expect("interval send failed");
-                thread::sleep(Duration::from_millis(60_000));
-            }
-        });
         status_bar
     };
 
+    let config_path = config.path.clone();
+    let tx_query_watch = tx_query.clone();
+    thread::spawn(move || worker::watch_config(config_path, tx_query_watch));
+
     let rx = {
-        let (tx, rx) = channel::<String>();
+        let (tx, rx) = channel::<Refresh>();
         let stopper = status_bar.stopper();
-        thread::spawn(move || {
-            let gl = &mut Gitlab::new(
-                config.gitlab_url.as_str(),
-                config.token.as_str(),
-            );
-
-            for reason in rx_query.iter() {
-                println!("refreshing: {:}", reason);
-                let title = make_title(&config, gl);
-                tx.send(title).expect("worker send failed");
-                stopper.stop();
-            }
-        });
+        thread::spawn(move || worker::run(config, rx_query, tx, Some(stopper)));
         rx
     };
 
     loop {
         status_bar.run(true);
-        while let Ok(title) = rx.try_recv() {
-            status_bar.set_title(title.as_str());
+        loop {
+            match rx.try_recv() {
+                Ok(refresh) => status_bar.update(&refresh),
+                Err(TryRecvError::Empty) => break,
+                // the worker only hangs up on shutdown
+                Err(TryRecvError::Disconnected) => exit(0),
+            }
         }
     }
 }
 
-fn make_title(config: &Config, gl: &mut Gitlab) -> String {
-    let projects = &config.project;
-    let title: String = projects
-        .iter()
-        .map(|p| {
-            let requires_merge = gl
-                .merge_request_count(&p.name, &p.ignore_users)
-                .map(|i| format!("{:}", i))
-                .unwrap_or("⨳".to_string());
-
-            let status = gl
-                .pipeline_status(&p.name, "master")
-                .map(status_icon)
-                .unwrap_or_else(|e| {
-                    println!("error: {:?}", e);
-                    "?"
-                });
-
-            let merge_requests = gl
-                .user_merge_requests(&p.name, &p.branch_users)
-                .map(|v| {
-                    v.iter()
-                        .map(|mrs| format!("{:}{:} ", mrs.branch, status_icon(mrs.status)))
-                        .collect::<String>()
-                })
-                .unwrap_or_else(|e| {
-                    println!("error: {:?}", e);
-                    "⨳".to_string()
-                });
-
-            let mut title = String::new();
-            if status != "" || requires_merge != "0" || merge_requests != "" {
-                title.push_str(&p.title);
-                if status != "" {
-                    title.push_str(status);
-                } else {
-                    title.push(' ');
-                }
-                if requires_merge != "0" {
-                    title.push_str(&*requires_merge);
-                    title.push(' ');
-                }
-                if merge_requests != "" {
-                    title.push_str(&*merge_requests);
-                }
+fn new_status_surface(title: &str, tx: Sender<RefreshRequest>, frontend: Frontend) -> Box<dyn StatusSurface> {
+    match frontend {
+        #[cfg(feature = "macos")]
+        Frontend::StatusBar => Box::new(macos::OSXStatusBar::new(title, tx)),
+        #[cfg(all(feature = "tray", not(feature = "macos")))]
+        Frontend::StatusBar => match tray::TrayStatus::new(title, tx.clone()) {
+            Ok(tray) => Box::new(tray),
+            Err(e) => {
+                eprintln!("error starting tray, falling back to terminal: {:?}", e);
+                Box::new(terminal::TerminalStatus::new(title, tx))
             }
-            title
-        })
-        .collect::<String>()
-        .trim()
-        .to_string();
-
-    if title == "" {
-        config.title.to_string()
-    } else {
-        title
-    }
-}
-
-fn status_icon(status: PipelineStatus) -> &'static str {
-    match status {
-        PipelineStatus::Running => "🏃",
-        PipelineStatus::Pending => "🕗",
-        PipelineStatus::Success => "",
-        PipelineStatus::Failed => "💩",
-        PipelineStatus::Canceled => "✋",
-        PipelineStatus::Skipped => "⦳",
-        PipelineStatus::Manual => "",
+        },
+        #[cfg(not(any(feature = "macos", feature = "tray")))]
+        Frontend::StatusBar => Box::new(terminal::TerminalStatus::new(title, tx)),
+        Frontend::Terminal => Box::new(terminal::TerminalStatus::new(title, tx)),
+        Frontend::Waybar => Box::new(waybar::WaybarStatus::new(title, tx)),
+        Frontend::Dashboard => unreachable!("the dashboard is not a status surface"),
     }
 }
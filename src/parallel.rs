@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// Budget of threads shared by every `map` and `join` using it, nested or
+/// not, so `parallelism` bounds threads as well as requests.  The calling
+/// thread always does its share of the work, so nothing waits for a thread
+/// to become free: work is only spread wider when the budget allows.
+pub struct Threads {
+    /// Threads that may be started besides the ones already working.
+    free: AtomicUsize,
+}
+
+/// Threads taken from the budget, given back when dropped.
+struct Reserved<'a> {
+    threads: &'a Threads,
+    count: usize,
+}
+
+impl<'a> Drop for Reserved<'a> {
+    fn drop(&mut self) {
+        self.threads.free.fetch_add(self.count, Ordering::SeqCst);
+    }
+}
+
+impl Threads {
+    /// A budget of `limit` threads in all, the calling one included.
+    pub fn new(limit: usize) -> Threads {
+        Threads {
+            free: AtomicUsize::new(limit.max(1) - 1),
+        }
+    }
+
+    /// Takes up to `wanted` threads, as many as are free.
+    fn reserve(&self, wanted: usize) -> Reserved<'_> {
+        let taken = self
+            .free
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |free| Some(free - free.min(wanted)))
+            .map(|free| free.min(wanted))
+            .unwrap_or(0);
+        Reserved {
+            threads: self,
+            count: taken,
+        }
+    }
+
+    /// Maps `f` over `items`, keeping the input order.
+    pub fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        let reserved = self.reserve(items.len().saturating_sub(1));
+        if reserved.count == 0 {
+            return items.iter().map(f).collect();
+        }
+
+        let next = AtomicUsize::new(0);
+        let work = || {
+            let mut done = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                match items.get(i) {
+                    Some(item) => done.push((i, f(item))),
+                    None => return done,
+                }
+            }
+        };
+        let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..reserved.count).map(|_| scope.spawn(work)).collect();
+            let mut results = work();
+            for h in handles {
+                results.extend(h.join().expect("parallel map worker panicked"));
+            }
+            results
+        });
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, r)| r).collect()
+    }
+
+    /// Runs `a` and `b`, on two threads if one is free.
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB,
+        RA: Send,
+    {
+        let reserved = self.reserve(1);
+        if reserved.count == 0 {
+            return (a(), b());
+        }
+        thread::scope(|scope| {
+            let a = scope.spawn(a);
+            let b = b();
+            (a.join().expect("parallel join worker panicked"), b)
+        })
+    }
+}
+
+/// Counting semaphore bounding how many requests are in flight at once,
+/// however many threads are issuing them.
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock().expect("semaphore poisoned");
+        while *available == 0 {
+            available = self.released.wait(available).expect("semaphore poisoned");
+        }
+        *available -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().expect("semaphore poisoned") += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn map_keeps_order() {
+        let items: Vec<usize> = (0..50).collect();
+        assert_eq!(Threads::new(4).map(&items, |i| i * 2), items.iter().map(|i| i * 2).collect::<Vec<_>>());
+        assert_eq!(Threads::new(1).map(&items, |i| i + 1)[49], 50);
+    }
+
+    #[test]
+    fn nested_work_stays_within_the_budget() {
+        let threads = Threads::new(3);
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..4).collect();
+        threads.map(&items, |_| {
+            threads.map(&items, |_| {
+                threads.join(
+                    || (),
+                    || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(5));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    },
+                )
+            })
+        });
+        assert!(most.load(Ordering::SeqCst) <= 3);
+        assert_eq!(threads.free.load(Ordering::SeqCst), 2);
+    }
+}
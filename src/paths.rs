@@ -0,0 +1,40 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Where this program keeps its own data: `$XDG_DATA_HOME` (by default
+/// `~/.local/share`) or `~/Library/Application Support` on macOS.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "macos") {
+        home()?.join("Library/Application Support")
+    } else {
+        match env_dir("XDG_DATA_HOME") {
+            Some(dir) => dir,
+            None => home()?.join(".local/share"),
+        }
+    };
+    Some(base.join(env!("CARGO_PKG_NAME")))
+}
+
+/// Where the user keeps this program's config: `$XDG_CONFIG_HOME` (by
+/// default `~/.config`) or `~/Library/Application Support` on macOS.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "macos") {
+        home()?.join("Library/Application Support")
+    } else {
+        match env_dir("XDG_CONFIG_HOME") {
+            Some(dir) => dir,
+            None => home()?.join(".config"),
+        }
+    };
+    Some(base.join(env!("CARGO_PKG_NAME")))
+}
+
+fn home() -> Option<PathBuf> {
+    env_dir("HOME")
+}
+
+/// An absolute directory from the environment; the XDG spec says relative
+/// ones are to be ignored.
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name).map(PathBuf::from).filter(|p| p.is_absolute())
+}
@@ -0,0 +1,270 @@
+use chrono::Utc;
+
+use crate::config::Config;
+use crate::icons::IconSet;
+use crate::gitlab::ErrorKind;
+use crate::status::{problems, Problem, ProjectStatus};
+use crate::template::Template;
+
+/// Layout used when neither the project nor the config sets `title_template`.
+pub const DEFAULT_TEMPLATE: &str = "{title}{status}[ {review_count}][ {failing_branches}][ ({age})]";
+
+/// Placeholders available in `title_template`.  `age` is only set while a
+/// project shows its last known status because refreshing it failed.
+pub const PLACEHOLDERS: &[&str] = &["title", "name", "status", "ref", "review_count", "failing_branches", "age"];
+
+/// Added to tooltips after a refresh that GitLab rate limited.
+pub const THROTTLED: &str = "GitLab is rate limiting requests, statuses may lag behind";
+
+/// Compact one-line title for the menu bar.  Projects with nothing to report
+/// are left out; if none have anything, `config.title` is shown instead.
+/// When GitLab cannot be reached at all the offline icon is added.
+pub fn make_title(config: &Config, projects: &[ProjectStatus]) -> String {
+    let icons = IconSet::from_config(&config.icons).unwrap_or_else(|e| {
+        error!("error in [icons]: {:}", e);
+        IconSet::preset("emoji").expect("missing emoji icons")
+    });
+    // nothing per project is worth showing when every request is refused
+    let problems = problems(projects);
+    if problems.iter().any(Problem::is_token) {
+        return format!("{:}{:}", config.title, icons.error);
+    }
+
+    let default = template(config.title_template.as_deref(), DEFAULT_TEMPLATE);
+    let title = projects
+        .iter()
+        .map(|p| {
+            let project_template = config
+                .project
+                .iter()
+                .find(|c| c.name == p.name)
+                .and_then(|c| c.title_template.as_deref());
+            match project_template {
+                Some(source) => project_title(&template(Some(source), DEFAULT_TEMPLATE), &icons, p),
+                None => project_title(&default, &icons, p),
+            }
+        })
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let title = if title.is_empty() { config.title.to_string() } else { title };
+    if problems.contains(&Problem::Offline) {
+        format!("{:}{:}", title, icons.offline)
+    } else {
+        title
+    }
+}
+
+/// How old a stale status is, such as `5m`, or empty for a fresh one.
+pub fn age(p: &ProjectStatus) -> String {
+    if !p.is_stale() {
+        return String::new();
+    }
+    let age = Utc::now().signed_duration_since(p.as_of());
+    if age.num_days() > 0 {
+        format!("{:}d", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{:}h", age.num_hours())
+    } else {
+        format!("{:}m", age.num_minutes().max(1))
+    }
+}
+
+/// Parses a configured template, falling back to `default` if it is invalid.
+fn template(source: Option<&str>, default: &str) -> Template {
+    source
+        .and_then(|source| {
+            Template::parse(source, PLACEHOLDERS)
+                .map_err(|e| error!("error in title_template {:?}: {:}", source, e))
+                .ok()
+        })
+        .unwrap_or_else(|| Template::parse(default, PLACEHOLDERS).expect("invalid default template"))
+}
+
+fn project_title(template: &Template, icons: &IconSet, p: &ProjectStatus) -> String {
+    let status = match &p.pipeline {
+        Ok(s) => icons.status(*s),
+        Err(e) if e.kind == ErrorKind::NotFound => &icons.error,
+        Err(_) => &icons.unknown,
+    };
+
+    let review_count = match &p.review_count {
+        Ok(0) => String::new(),
+        Ok(i) => format!("{:}", i),
+        Err(_) => icons.error.clone(),
+    };
+
+    let failing_branches = match &p.failing {
+        Ok(v) => v
+            .iter()
+            .map(|mrs| format!("{:}{:}", mrs.branch, icons.status(mrs.status)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Err(_) => icons.error.clone(),
+    };
+
+    if status.is_empty() && review_count.is_empty() && failing_branches.is_empty() {
+        return String::new();
+    }
+    let age = age(p);
+
+    template
+        .render(&|name| match name {
+            "title" => p.title.clone(),
+            "name" => p.name.clone(),
+            "status" => status.to_string(),
+            "ref" => p.ref_name.clone().unwrap_or_default(),
+            "review_count" => review_count.clone(),
+            "failing_branches" => failing_branches.clone(),
+            "age" => age.clone(),
+            _ => String::new(),
+        })
+        .trim()
+        .to_string()
+}
+
+/// Longer description with one line per problem and per project, for
+/// tooltips and menus.
+pub fn tooltip(projects: &[ProjectStatus]) -> String {
+    problems(projects)
+        .iter()
+        .map(Problem::to_string)
+        .chain(projects.iter().map(tooltip_line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn tooltip_line(p: &ProjectStatus) -> String {
+    let title = if p.is_stale() {
+        format!("{:} (as of {:} ago)", p.title, age(p))
+    } else {
+        p.title.clone()
+    };
+    let mut line = format!(
+        "{:}: {:}, {:} to review, failing: {:}",
+        title,
+        match (&p.pipeline, &p.ref_name) {
+            (Ok(s), Some(ref_name)) => format!("{:?} on {:}", s, ref_name),
+            (Ok(s), None) => format!("{:?}", s),
+            (Err(_), _) => "unknown".to_string(),
+        },
+        match &p.review_count {
+            Ok(n) => format!("{:}", n),
+            Err(_) => "unknown".to_string(),
+        },
+        match &p.failing {
+            Ok(v) if v.is_empty() => "none".to_string(),
+            Ok(v) => v.iter().map(|mr| mr.branch.as_str()).collect::<Vec<_>>().join(", "),
+            Err(_) => "unknown".to_string(),
+        }
+    );
+    let errors = p.errors();
+    if !errors.is_empty() {
+        line.push_str(&format!(" ({:})", errors.join("; ")));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gitlab::{MergeRequestStatus, PipelineStatus};
+    use crate::status::{FetchError, StaleField};
+    use chrono::Duration;
+
+    fn config() -> Config {
+        toml::from_str("token = \"x\"\nicons.preset = \"ascii\"").expect("test config")
+    }
+
+    #[test]
+    fn passing_projects_show_the_title() {
+        let projects = [ProjectStatus::example(Ok(PipelineStatus::Success), Ok(0))];
+        assert_eq!(make_title(&config(), &projects), "GitLab");
+        assert_eq!(tooltip(&projects), "app: Success on main, 0 to review, failing: none");
+    }
+
+    #[test]
+    fn failures_and_reviews_are_shown() {
+        let mut failing = ProjectStatus::example(Ok(PipelineStatus::Failed), Ok(2));
+        failing.failing = Ok(vec![MergeRequestStatus {
+            branch: "fix".to_string(),
+            status: PipelineStatus::Failed,
+            web_url: String::new(),
+        }]);
+        let projects = [failing, ProjectStatus::example(Ok(PipelineStatus::Running), Ok(0))];
+        assert_eq!(make_title(&config(), &projects), "app! 2 fix! app>");
+        assert_eq!(
+            tooltip(&projects),
+            "app: Failed on main, 2 to review, failing: fix\napp: Running on main, 0 to review, failing: none"
+        );
+    }
+
+    #[test]
+    fn errors_are_marked() {
+        let projects = [
+            ProjectStatus::example(Err(FetchError::example(ErrorKind::Other)), Err(FetchError::example(ErrorKind::Other))),
+            ProjectStatus::example(Err(FetchError::example(ErrorKind::NotFound)), Ok(0)),
+        ];
+        assert_eq!(make_title(&config(), &projects), "app? # app#");
+        let tooltip = tooltip(&projects);
+        let lines: Vec<&str> = tooltip.lines().collect();
+        assert_eq!(lines[0], "project group/app not found, check its name in the config");
+        assert_eq!(
+            lines[1],
+            "app: unknown, unknown to review, failing: none (Other error; Other error)"
+        );
+    }
+
+    #[test]
+    fn stale_projects_show_their_age() {
+        let mut stale = ProjectStatus::example(Ok(PipelineStatus::Failed), Ok(1));
+        stale.stale = vec![StaleField {
+            field: "review count".to_string(),
+            error: FetchError::example(ErrorKind::Other),
+            fetched_at: Utc::now() - Duration::minutes(10),
+        }];
+        let projects = [stale];
+        assert_eq!(make_title(&config(), &projects), "app! 1 (10m)");
+        assert_eq!(
+            tooltip(&projects),
+            "app (as of 10m ago): Failed on main, 1 to review, failing: none (review count: Other error)"
+        );
+    }
+
+    #[test]
+    fn restored_projects_are_stale() {
+        let mut restored = ProjectStatus::example(Ok(PipelineStatus::Failed), Ok(0));
+        restored.fetched_at = Utc::now() - Duration::hours(3);
+        restored.restored = true;
+        assert_eq!(make_title(&config(), &[restored]), "app! (3h)");
+    }
+
+    #[test]
+    fn offline_is_added_to_the_title() {
+        let mut offline = ProjectStatus::example(Ok(PipelineStatus::Failed), Ok(0));
+        offline.stale = vec![StaleField {
+            field: "pipeline on main".to_string(),
+            error: FetchError::example(ErrorKind::Network),
+            fetched_at: Utc::now() - Duration::days(2),
+        }];
+        let projects = [offline];
+        assert_eq!(make_title(&config(), &projects), "app! (2d) offline");
+        assert!(tooltip(&projects).starts_with("cannot reach GitLab, showing the last known status\n"));
+    }
+
+    #[test]
+    fn rejected_token_hides_projects() {
+        let projects = [ProjectStatus::example(Err(FetchError::example(ErrorKind::Unauthorized)), Ok(3))];
+        assert_eq!(make_title(&config(), &projects), "GitLab#");
+        assert!(tooltip(&projects).starts_with("GitLab rejected the token"));
+    }
+
+    #[test]
+    fn project_templates_override_the_default() {
+        let mut config = config();
+        config.title_template = Some("{name}:{status}".to_string());
+        let projects = [ProjectStatus::example(Ok(PipelineStatus::Failed), Ok(2))];
+        assert_eq!(make_title(&config, &projects), "group/app:!");
+    }
+}
@@ -0,0 +1,104 @@
+extern crate serde_json;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::paths::data_dir;
+use crate::status::ProjectStatus;
+
+/// The last refreshed statuses, shown right away on the next launch.
+fn state_file() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("state.json"))
+}
+
+/// Statuses saved by a previous run for the configured projects, in config
+/// order and marked as restored.  Missing or unreadable state is not an
+/// error, there is simply nothing to show yet.
+pub fn load(config: &Config) -> Vec<ProjectStatus> {
+    match state_file() {
+        Some(path) => load_from(&path, config),
+        None => Vec::new(),
+    }
+}
+
+fn load_from(path: &Path, config: &Config) -> Vec<ProjectStatus> {
+    let saved: Vec<ProjectStatus> = match File::open(path) {
+        Ok(file) => match serde_json::from_reader(file) {
+            Ok(saved) => saved,
+            Err(e) => {
+                error!("ignoring saved state: {:}", e);
+                return Vec::new();
+            }
+        },
+        Err(_) => return Vec::new(),
+    };
+    config
+        .project
+        .iter()
+        .filter_map(|p| {
+            let mut status = saved.iter().find(|s| s.name == p.name)?.clone();
+            status.title = p.title().to_string();
+            status.restored = true;
+            Some(status)
+        })
+        .collect()
+}
+
+/// Replaces the saved state with `projects`.
+pub fn save(projects: &[ProjectStatus]) -> Result<(), Box<dyn Error>> {
+    let path = state_file().ok_or("no data directory, HOME is not set")?;
+    save_to(&path, projects)
+}
+
+fn save_to(path: &Path, projects: &[ProjectStatus]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // write then rename, so a crash never leaves half a file behind
+    let partial = path.with_extension("json.tmp");
+    serde_json::to_writer(File::create(&partial)?, projects)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gitlab::PipelineStatus;
+    use std::env;
+
+    fn status(name: &str, title: &str) -> ProjectStatus {
+        let mut status = ProjectStatus::example(Ok(PipelineStatus::Success), Ok(1));
+        status.name = name.to_string();
+        status.title = title.to_string();
+        status
+    }
+
+    #[test]
+    fn saved_state_is_restored_for_configured_projects() {
+        let path = env::temp_dir().join(format!("cl-worktool-state-{:}.json", std::process::id()));
+        save_to(&path, &[status("g/a", "a"), status("g/gone", "gone"), status("g/b", "b")]).expect("save state");
+
+        let config: Config = toml::from_str(
+            "token = \"x\"\n[[project]]\nname = \"g/b\"\n[[project]]\nname = \"g/a\"\ntitle = \"renamed\"",
+        )
+        .expect("test config");
+        let restored = load_from(&path, &config);
+        let _ = fs::remove_file(&path);
+
+        let names: Vec<&str> = restored.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["g/b", "g/a"]);
+        assert_eq!(restored[0].title, "g/b");
+        assert_eq!(restored[1].title, "renamed");
+        assert!(restored.iter().all(|p| p.restored));
+        assert!(matches!(restored[1].pipeline, Ok(PipelineStatus::Success)));
+    }
+
+    #[test]
+    fn missing_state_restores_nothing() {
+        let config: Config = toml::from_str("token = \"x\"\n[[project]]\nname = \"g/a\"").expect("test config");
+        assert!(load_from(&env::temp_dir().join("cl-worktool-no-such-state.json"), &config).is_empty());
+    }
+}
@@ -0,0 +1,494 @@
+extern crate glob;
+
+use std::collections::HashSet;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use self::glob::Pattern;
+
+use crate::config::{is_ref_pattern, Project};
+use crate::gitlab::{self, ErrorKind, Gitlab, MergeRequestStatus, PipelineStatus};
+
+/// A fetched value, or the error that stopped it being fetched.  Each field
+/// fails independently so the rest can still be shown.
+pub type Field<T> = Result<T, FetchError>;
+
+/// A `gitlab::Error` reduced to what the frontends need, so statuses stay
+/// cheap to clone and send between threads.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl FetchError {
+    /// A problem found without GitLab reporting an error, such as a bad
+    /// ref pattern.
+    fn other(message: String) -> FetchError {
+        FetchError {
+            kind: ErrorKind::Other,
+            message,
+        }
+    }
+}
+
+impl From<gitlab::Error> for FetchError {
+    fn from(e: gitlab::Error) -> FetchError {
+        FetchError {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Something wrong with the setup rather than with a pipeline, which the
+/// frontends point out on their own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// GitLab answered 401, so a new token is needed.
+    TokenRejected,
+    /// The token could not be read from its source, with the reason.
+    TokenUnreadable(String),
+    /// GitLab answered 404 for this `Project::name`.
+    ProjectNotFound(String),
+    /// No project could reach GitLab.
+    Offline,
+}
+
+impl Problem {
+    /// Whether no request can succeed until the token is fixed.
+    pub fn is_token(&self) -> bool {
+        matches!(self, Problem::TokenRejected | Problem::TokenUnreadable(_))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::TokenRejected => write!(f, "GitLab rejected the token, replace it and refresh"),
+            Problem::TokenUnreadable(message) => write!(f, "{:}", message),
+            Problem::ProjectNotFound(name) => write!(f, "project {:} not found, check its name in the config", name),
+            Problem::Offline => write!(f, "cannot reach GitLab, showing the last known status"),
+        }
+    }
+}
+
+/// The problems behind the errors in `projects`, most serious first.
+pub fn problems(projects: &[ProjectStatus]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if projects.iter().any(|p| p.has_error(ErrorKind::Unauthorized)) {
+        problems.push(Problem::TokenRejected);
+    }
+    let unreadable = projects.iter().find_map(|p| p.errors_of(ErrorKind::Token).next().map(|e| e.message.clone()));
+    if let Some(message) = unreadable {
+        problems.push(Problem::TokenUnreadable(message));
+    }
+    if !projects.is_empty() && projects.iter().all(|p| p.has_error(ErrorKind::Network)) {
+        problems.push(Problem::Offline);
+    }
+    problems.extend(
+        projects
+            .iter()
+            .filter(|p| p.has_error(ErrorKind::NotFound))
+            .map(|p| Problem::ProjectNotFound(p.name.clone())),
+    );
+    problems
+}
+
+/// Everything known about one project after a refresh, independent of how
+/// it is rendered.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectStatus {
+    pub title: String,
+    pub name: String,
+    /// Every monitored ref, in config order with wildcards expanded.
+    pub refs: Vec<RefStatus>,
+    /// Ref with the most severe status, see `pipeline`.
+    pub ref_name: Option<String>,
+    /// Most severe pipeline status across `refs`.
+    pub pipeline: Field<PipelineStatus>,
+    /// Open merge requests waiting on a review from us.
+    pub review_count: Field<usize>,
+    /// Merge requests from `branch_users` whose latest pipeline failed.
+    pub failing: Field<Vec<MergeRequestStatus>>,
+    pub fetched_at: DateTime<Utc>,
+    /// Fields that failed to refresh and show their last good value instead.
+    /// Empty when everything is fresh.
+    #[serde(default)]
+    pub stale: Vec<StaleField>,
+    /// Loaded from the state file at startup and not refreshed since.
+    #[serde(skip)]
+    pub restored: bool,
+}
+
+/// A field kept from an earlier refresh because fetching it again failed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StaleField {
+    /// Which field, such as `review count` or `pipeline on main`.
+    pub field: String,
+    pub error: FetchError,
+    /// When the value that was kept had been fetched.
+    pub fetched_at: DateTime<Utc>,
+}
+
+const REVIEW_COUNT: &str = "review count";
+const FAILING: &str = "failing branches";
+
+fn ref_field(ref_name: &str) -> String {
+    format!("pipeline on {:}", ref_name)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RefStatus {
+    pub ref_name: String,
+    /// Status of the latest non-scheduled pipeline on the ref.
+    pub pipeline: Field<PipelineStatus>,
+}
+
+impl ProjectStatus {
+    /// Whether any of this is older, restored at startup or kept in place of
+    /// a field that failed to refresh.
+    pub fn is_stale(&self) -> bool {
+        self.restored || !self.stale.is_empty()
+    }
+
+    /// When the oldest value shown was fetched.
+    pub fn as_of(&self) -> DateTime<Utc> {
+        self.stale.iter().map(|s| s.fetched_at).fold(self.fetched_at, DateTime::min)
+    }
+
+    /// When the value shown for `field` was fetched.
+    fn fetched_at(&self, field: &str) -> DateTime<Utc> {
+        self.stale
+            .iter()
+            .find(|s| s.field == field)
+            .map_or(self.fetched_at, |s| s.fetched_at)
+    }
+
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors: Vec<String> = self
+            .stale
+            .iter()
+            .map(|s| format!("{:}: {:}", s.field, s.error))
+            .collect();
+        match &self.pipeline {
+            Err(e) => errors.push(e.to_string()),
+            Ok(_) => errors.extend(self.refs.iter().filter_map(|r| match &r.pipeline {
+                Err(e) => Some(format!("{:}: {:}", r.ref_name, e)),
+                Ok(_) => None,
+            })),
+        }
+        if let Err(e) = &self.review_count {
+            errors.push(e.to_string());
+        }
+        if let Err(e) = &self.failing {
+            errors.push(e.to_string());
+        }
+        errors
+    }
+
+    /// Whether any field, or the refresh this status stands in for, failed
+    /// with an error of this kind.
+    pub fn has_error(&self, kind: ErrorKind) -> bool {
+        self.errors_of(kind).next().is_some()
+    }
+
+    fn errors_of(&self, kind: ErrorKind) -> impl Iterator<Item = &FetchError> {
+        self.field_errors()
+            .into_iter()
+            .chain(self.stale.iter().map(|s| &s.error))
+            .filter(move |e| e.kind == kind)
+    }
+
+    fn field_errors(&self) -> Vec<&FetchError> {
+        let pipeline = self.refs.iter().map(|r| &r.pipeline).chain(Some(&self.pipeline));
+        pipeline
+            .filter_map(|f| f.as_ref().err())
+            .chain(self.review_count.as_ref().err())
+            .chain(self.failing.as_ref().err())
+            .collect()
+    }
+}
+
+/// `fresh`, except that each field which failed to fetch keeps its value from
+/// `previous`, if that had one, and is marked stale with the new error.
+/// When the refs themselves could not be listed, every ref is kept.
+pub fn keep_last_good(mut fresh: ProjectStatus, previous: Option<&ProjectStatus>) -> ProjectStatus {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return fresh,
+    };
+    let mut stale = Vec::new();
+
+    match (&fresh.pipeline, fresh.refs.is_empty()) {
+        (Err(error), true) => {
+            let error = error.clone();
+            fresh.refs = previous.refs.clone();
+            for r in fresh.refs.iter().filter(|r| r.pipeline.is_ok()) {
+                let field = ref_field(&r.ref_name);
+                stale.push(StaleField {
+                    fetched_at: previous.fetched_at(&field),
+                    field,
+                    error: error.clone(),
+                });
+            }
+        }
+        _ => {
+            for r in fresh.refs.iter_mut() {
+                let kept = previous.refs.iter().find(|p| p.ref_name == r.ref_name);
+                keep(&ref_field(&r.ref_name), &mut r.pipeline, kept.map(|p| &p.pipeline), previous, &mut stale);
+            }
+        }
+    }
+    if !fresh.refs.is_empty() {
+        let (ref_name, pipeline) = worst(&fresh.refs);
+        fresh.ref_name = ref_name;
+        fresh.pipeline = pipeline;
+    }
+    keep(REVIEW_COUNT, &mut fresh.review_count, Some(&previous.review_count), previous, &mut stale);
+    keep(FAILING, &mut fresh.failing, Some(&previous.failing), previous, &mut stale);
+
+    fresh.stale = stale;
+    fresh
+}
+
+/// Replaces a failed `fresh` value with a good `kept` one from `previous`.
+fn keep<T: Clone>(
+    field: &str,
+    fresh: &mut Field<T>,
+    kept: Option<&Field<T>>,
+    previous: &ProjectStatus,
+    stale: &mut Vec<StaleField>,
+) {
+    if let (Err(error), Some(Ok(kept))) = (&*fresh, kept) {
+        stale.push(StaleField {
+            field: field.to_string(),
+            error: error.clone(),
+            fetched_at: previous.fetched_at(field),
+        });
+        *fresh = Ok(kept.clone());
+    }
+}
+
+/// Fetches pipelines, review count and failing branches concurrently.
+pub fn fetch(gl: &Gitlab, p: &Project) -> ProjectStatus {
+    let ((review_count, failing), (refs, ref_name, pipeline)) =
+        gl.threads().join(|| fetch_merge_requests(gl, p), || fetch_pipelines(gl, p));
+    if let Err(e) = &pipeline {
+        error!("error: {:}", e);
+    }
+
+    ProjectStatus {
+        title: p.title().to_string(),
+        name: p.name.clone(),
+        refs,
+        ref_name,
+        pipeline,
+        review_count,
+        failing,
+        fetched_at: Utc::now(),
+        stale: Vec::new(),
+        restored: false,
+    }
+}
+
+/// Review count and failing branches, both computed from one listing of the
+/// project's open merge requests.
+fn fetch_merge_requests(gl: &Gitlab, p: &Project) -> (Field<usize>, Field<Vec<MergeRequestStatus>>) {
+    let snapshot = match gl.snapshot(&p.name) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            error!("error: {:}", e);
+            let e = FetchError::from(e);
+            return (Err(e.clone()), Err(e));
+        }
+    };
+    let (failing, review_count) = gl.threads().join(
+        || gl.user_merge_requests(&snapshot, &p.branch_users),
+        || gl.merge_request_count(&snapshot, &p.ignore_users),
+    );
+    for e in review_count.as_ref().err().into_iter().chain(failing.as_ref().err()) {
+        error!("error: {:}", e);
+    }
+    (review_count.map_err(FetchError::from), failing.map_err(FetchError::from))
+}
+
+/// Status of every monitored ref, and the most severe of them with its ref.
+pub fn fetch_pipelines(gl: &Gitlab, p: &Project) -> (Vec<RefStatus>, Option<String>, Field<PipelineStatus>) {
+    let refs: Vec<RefStatus> = match monitored_refs(gl, p) {
+        Ok(refs) => gl.threads().map(&refs, |ref_name| RefStatus {
+            pipeline: gl.pipeline_status(&p.name, ref_name).map_err(FetchError::from),
+            ref_name: ref_name.clone(),
+        }),
+        Err(e) => return (Vec::new(), None, Err(e)),
+    };
+    let (ref_name, pipeline) = worst(&refs);
+    (refs, ref_name, pipeline)
+}
+
+/// The most severe status among `refs` and its ref, or the first error if
+/// none could be fetched.
+fn worst(refs: &[RefStatus]) -> (Option<String>, Field<PipelineStatus>) {
+    let worst = refs
+        .iter()
+        .filter_map(|r| r.pipeline.as_ref().ok().map(|s| (r, *s)))
+        .max_by_key(|(_, s)| s.severity());
+    match worst {
+        Some((r, status)) => (Some(r.ref_name.clone()), Ok(status)),
+        None => {
+            let error = refs
+                .iter()
+                .find_map(|r| r.pipeline.as_ref().err().cloned())
+                .unwrap_or_else(|| FetchError::other("no refs to monitor".to_string()));
+            (None, Err(error))
+        }
+    }
+}
+
+/// The `ref` (or default branch) followed by `refs`, with wildcard patterns
+/// matched against the project's branches.
+fn monitored_refs(gl: &Gitlab, p: &Project) -> Field<Vec<String>> {
+    let mut refs = Vec::new();
+    match &p.ref_name {
+        Some(ref_name) => refs.push(ref_name.clone()),
+        None => match gl.default_branch(&p.name) {
+            Ok(Some(branch)) => refs.push(branch),
+            Ok(None) if !p.refs.is_empty() => {}
+            Ok(None) => return Err(FetchError::other("project has no default branch".to_string())),
+            Err(e) => return Err(e.into()),
+        },
+    }
+
+    let mut branches: Option<Vec<String>> = None;
+    for pattern in &p.refs {
+        if !is_ref_pattern(pattern) {
+            refs.push(pattern.clone());
+            continue;
+        }
+        let glob = Pattern::new(pattern)
+            .map_err(|e| FetchError::other(format!("invalid ref pattern {:?}: {:}", pattern, e)))?;
+        if branches.is_none() {
+            branches = Some(gl.branch_names(&p.name)?);
+        }
+        let mut matching: Vec<String> = branches
+            .iter()
+            .flatten()
+            .filter(|b| glob.matches(b))
+            .cloned()
+            .collect();
+        matching.sort();
+        refs.extend(matching);
+    }
+
+    let mut seen = HashSet::new();
+    refs.retain(|r| seen.insert(r.clone()));
+    Ok(refs)
+}
+
+#[cfg(test)]
+impl FetchError {
+    /// An error of `kind` for tests.
+    pub fn example(kind: ErrorKind) -> FetchError {
+        FetchError {
+            kind,
+            message: format!("{:?} error", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ProjectStatus {
+    /// `group/app` titled `app`, monitoring only `main`, fetched just now.
+    pub fn example(pipeline: Field<PipelineStatus>, review_count: Field<usize>) -> ProjectStatus {
+        ProjectStatus {
+            title: "app".to_string(),
+            name: "group/app".to_string(),
+            refs: vec![RefStatus {
+                ref_name: "main".to_string(),
+                pipeline: pipeline.clone(),
+            }],
+            ref_name: Some("main".to_string()),
+            pipeline,
+            review_count,
+            failing: Ok(Vec::new()),
+            fetched_at: Utc::now(),
+            stale: Vec::new(),
+            restored: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn failed_field_keeps_only_its_previous_value() {
+        let mut previous = ProjectStatus::example(Ok(PipelineStatus::Success), Ok(2));
+        previous.fetched_at = Utc::now() - Duration::minutes(10);
+        let fresh = ProjectStatus::example(Ok(PipelineStatus::Failed), Err(FetchError::example(ErrorKind::Other)));
+
+        let merged = keep_last_good(fresh, Some(&previous));
+        assert!(matches!(merged.pipeline, Ok(PipelineStatus::Failed)));
+        assert!(matches!(merged.review_count, Ok(2)));
+        assert_eq!(merged.stale.len(), 1);
+        assert_eq!(merged.stale[0].field, REVIEW_COUNT);
+        assert_eq!(merged.stale[0].fetched_at, previous.fetched_at);
+        assert!(merged.is_stale());
+        assert_eq!(merged.as_of(), previous.fetched_at);
+    }
+
+    #[test]
+    fn failed_ref_keeps_its_pipeline() {
+        let previous = ProjectStatus::example(Ok(PipelineStatus::Running), Ok(0));
+        let fresh = ProjectStatus::example(Err(FetchError::example(ErrorKind::Network)), Ok(1));
+
+        let merged = keep_last_good(fresh, Some(&previous));
+        assert!(matches!(merged.pipeline, Ok(PipelineStatus::Running)));
+        assert!(matches!(merged.review_count, Ok(1)));
+        assert_eq!(merged.stale.len(), 1);
+        assert_eq!(merged.stale[0].field, "pipeline on main");
+        assert!(merged.has_error(ErrorKind::Network));
+    }
+
+    #[test]
+    fn unlisted_refs_keep_every_ref() {
+        let previous = ProjectStatus::example(Ok(PipelineStatus::Success), Ok(0));
+        let mut fresh = ProjectStatus::example(Err(FetchError::example(ErrorKind::NotFound)), Ok(0));
+        fresh.refs.clear();
+        fresh.ref_name = None;
+
+        let merged = keep_last_good(fresh, Some(&previous));
+        assert_eq!(merged.refs.len(), 1);
+        assert_eq!(merged.ref_name.as_deref(), Some("main"));
+        assert!(matches!(merged.pipeline, Ok(PipelineStatus::Success)));
+        assert!(merged.has_error(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn stale_field_stays_as_old_as_first_kept() {
+        let mut first = ProjectStatus::example(Ok(PipelineStatus::Success), Ok(3));
+        first.fetched_at = Utc::now() - Duration::hours(2);
+        let second = keep_last_good(ProjectStatus::example(Ok(PipelineStatus::Success), Err(FetchError::example(ErrorKind::Other))), Some(&first));
+        let third = keep_last_good(ProjectStatus::example(Ok(PipelineStatus::Success), Err(FetchError::example(ErrorKind::Other))), Some(&second));
+        assert!(matches!(third.review_count, Ok(3)));
+        assert_eq!(third.stale[0].fetched_at, first.fetched_at);
+    }
+
+    #[test]
+    fn fresh_status_is_not_stale() {
+        let previous = ProjectStatus::example(Ok(PipelineStatus::Success), Ok(0));
+        let merged = keep_last_good(ProjectStatus::example(Ok(PipelineStatus::Failed), Ok(1)), Some(&previous));
+        assert!(merged.stale.is_empty());
+        assert!(!merged.is_stale());
+    }
+}
@@ -0,0 +1,28 @@
+use crate::worker::Refresh;
+use crate::NSCallback;
+
+/// Handle used by the worker thread to wake a blocked `StatusSurface::run`.
+pub trait Stopper: Send {
+    fn stop(&self);
+}
+
+/// Something that can show a title, hold a menu of items and drive a run loop.
+///
+/// The macOS status bar is one implementation; the terminal is another.
+pub trait StatusSurface {
+    fn set_title(&self, title: &str);
+
+    /// Shows the result of a refresh.  Surfaces that can only show a title
+    /// don't need to override this.
+    fn update(&self, refresh: &Refresh) {
+        self.set_title(&refresh.title);
+    }
+
+    fn add_item(&mut self, item: &str, callback: NSCallback, selected: bool);
+
+    /// Runs the surface's event loop.  When `block` is set this only returns
+    /// once a `Stopper` from `stopper()` has been triggered.
+    fn run(&mut self, block: bool);
+
+    fn stopper(&self) -> Box<dyn Stopper>;
+}
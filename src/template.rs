@@ -0,0 +1,175 @@
+//! Title templates.
+//!
+//! `{name}` is replaced by the value of a placeholder.  Text inside `[` and
+//! `]` is a conditional section, only rendered when every placeholder
+//! directly inside it has a non-empty value; sections may nest.  A backslash
+//! makes the next character literal, so `\[` or `\{` can be written.
+
+/// A parsed template, rendered with `render`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Placeholder(String),
+    Section(Vec<Node>),
+}
+
+impl Template {
+    /// Parses `source`, rejecting placeholders not listed in `names`.
+    pub fn parse(source: &str, names: &[&str]) -> Result<Template, String> {
+        let mut chars = source.chars();
+        let nodes = parse_nodes(&mut chars, names, false)?;
+        Ok(Template { nodes })
+    }
+
+    /// Renders the template, asking `value` for each placeholder.
+    pub fn render<F: Fn(&str) -> String>(&self, value: &F) -> String {
+        render_nodes(&self.nodes, value, false).unwrap_or_default()
+    }
+}
+
+fn parse_nodes(chars: &mut std::str::Chars, names: &[&str], in_section: bool) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None if in_section => return Err("unclosed '['".to_string()),
+            None => break,
+        };
+        match c {
+            '\\' => match chars.next() {
+                Some(c) => text.push(c),
+                None => return Err("trailing '\\'".to_string()),
+            },
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err("unclosed '{'".to_string()),
+                    }
+                }
+                let name = name.trim();
+                if !names.contains(&name) {
+                    return Err(format!("unknown placeholder {{{:}}}, expected one of: {:}", name, names.join(", ")));
+                }
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text.split_off(0)));
+                }
+                nodes.push(Node::Placeholder(name.to_string()));
+            }
+            '}' => return Err("unmatched '}'".to_string()),
+            '[' => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text.split_off(0)));
+                }
+                nodes.push(Node::Section(parse_nodes(chars, names, true)?));
+            }
+            ']' if in_section => break,
+            ']' => return Err("unmatched ']'".to_string()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    Ok(nodes)
+}
+
+/// Returns `None` when `conditional` is set and a placeholder directly in
+/// `nodes` is empty.
+fn render_nodes<F: Fn(&str) -> String>(nodes: &[Node], value: &F, conditional: bool) -> Option<String> {
+    let mut out = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Placeholder(name) => {
+                let v = value(name);
+                if v.is_empty() && conditional {
+                    return None;
+                }
+                out.push_str(&v);
+            }
+            Node::Section(section) => {
+                if let Some(s) = render_nodes(section, value, true) {
+                    out.push_str(&s);
+                }
+            }
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: &[&str] = &["a", "b"];
+
+    /// Renders `source` with `a` set to `a` and `b` empty.
+    fn render(source: &str) -> String {
+        let template = Template::parse(source, NAMES).expect("valid template");
+        template.render(&|name| match name {
+            "a" => "A".to_string(),
+            _ => String::new(),
+        })
+    }
+
+    fn parse_error(source: &str) -> String {
+        Template::parse(source, NAMES).expect_err("invalid template")
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        assert_eq!(render("x{a}y{b}z"), "xAyz");
+        assert_eq!(render("{ a }"), "A");
+        assert_eq!(render(""), "");
+    }
+
+    #[test]
+    fn sections_need_every_placeholder() {
+        assert_eq!(render("[<{a}>]"), "<A>");
+        assert_eq!(render("[<{b}>]"), "");
+        assert_eq!(render("[{a}{b}]"), "");
+        assert_eq!(render("[text]"), "text");
+        assert_eq!(render("[]"), "");
+    }
+
+    #[test]
+    fn sections_nest() {
+        assert_eq!(render("[{a}[ {b}]!]"), "A!");
+        assert_eq!(render("[{b}[ {a}]!]"), "");
+        assert_eq!(render("[[{a}]]"), "A");
+        assert_eq!(render("[[]]"), "");
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        assert_eq!(render("\\[{a}\\]"), "[A]");
+        assert_eq!(render("\\{a\\}"), "{a}");
+        assert_eq!(render("\\\\"), "\\");
+        assert_eq!(render("[\\[{a}]"), "[A");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_eq!(parse_error("{c}"), "unknown placeholder {c}, expected one of: a, b");
+        assert_eq!(parse_error("[{}]"), "unknown placeholder {}, expected one of: a, b");
+    }
+
+    #[test]
+    fn unbalanced_brackets_are_rejected() {
+        assert_eq!(parse_error("{a"), "unclosed '{'");
+        assert_eq!(parse_error("[{a}"), "unclosed '['");
+        assert_eq!(parse_error("[[{a}]"), "unclosed '['");
+        assert_eq!(parse_error("a}"), "unmatched '}'");
+        assert_eq!(parse_error("{a}]"), "unmatched ']'");
+        assert_eq!(parse_error("{a}\\"), "trailing '\\'");
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use crate::surface::{StatusSurface, Stopper};
+use crate::worker::{Refresh, RefreshRequest};
+use crate::NSCallback;
+
+enum Event {
+    Stop,
+    Input(String),
+}
+
+/// Status surface that prints titles to stdout, one line per refresh, for
+/// `--headless` runs and builds without the menu bar.  Menu items are
+/// triggered by typing their name on stdin; diagnostics go to stderr so
+/// stdout only ever carries titles.
+pub struct TerminalStatus {
+    tx: Sender<RefreshRequest>,
+    items: Vec<(String, NSCallback)>,
+    events: Receiver<Event>,
+    event_tx: Sender<Event>,
+}
+
+pub struct TerminalStopper {
+    tx: Sender<Event>,
+}
+
+impl Stopper for TerminalStopper {
+    fn stop(&self) {
+        let _ = self.tx.send(Event::Stop);
+    }
+}
+
+impl TerminalStatus {
+    pub fn new(title: &str, tx: Sender<RefreshRequest>) -> TerminalStatus {
+        let status = TerminalStatus::listen(tx);
+        status.set_title(title);
+        status
+    }
+
+    /// Starts reading menu commands from stdin without printing anything.
+    pub fn listen(tx: Sender<RefreshRequest>) -> TerminalStatus {
+        let (event_tx, events) = channel();
+
+        let input_tx = event_tx.clone();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if input_tx.send(Event::Input(line)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        TerminalStatus {
+            tx,
+            items: Vec::new(),
+            events,
+            event_tx,
+        }
+    }
+
+    fn handle(&self, event: Event) -> bool {
+        match event {
+            Event::Stop => true,
+            Event::Input(line) => {
+                let line = line.trim();
+                match self
+                    .items
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (name, _))| name.eq_ignore_ascii_case(line))
+                {
+                    Some((i, (_, cb))) => cb(i as u64, &self.tx),
+                    None if line.is_empty() => {}
+                    None => {
+                        let names: Vec<&str> = self.items.iter().map(|(n, _)| n.as_str()).collect();
+                        eprintln!("unknown command {:?}, expected one of: {:}", line, names.join(", "));
+                    }
+                }
+                false
+            }
+        }
+    }
+}
+
+impl StatusSurface for TerminalStatus {
+    fn set_title(&self, title: &str) {
+        println!("{:}", title);
+    }
+
+    fn update(&self, refresh: &Refresh) {
+        self.set_title(&refresh.title);
+        if let Some(e) = &refresh.config_error {
+            eprintln!("config not reloaded: {:}", e);
+        }
+        for problem in refresh.problems() {
+            eprintln!("{:}", problem);
+        }
+    }
+
+    fn add_item(&mut self, item: &str, callback: NSCallback, _selected: bool) {
+        self.items.push((item.to_string(), callback));
+    }
+
+    fn run(&mut self, block: bool) {
+        if block {
+            while let Ok(event) = self.events.recv() {
+                if self.handle(event) {
+                    break;
+                }
+            }
+        } else {
+            while let Ok(event) = self.events.try_recv() {
+                if self.handle(event) {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn stopper(&self) -> Box<dyn Stopper> {
+        Box::new(TerminalStopper {
+            tx: self.event_tx.clone(),
+        })
+    }
+}
@@ -0,0 +1,243 @@
+extern crate ksni;
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use self::ksni::blocking::{Handle, TrayMethods};
+use self::ksni::menu::StandardItem;
+use self::ksni::{MenuItem, ToolTip, Tray};
+
+use crate::gitlab::PipelineStatus;
+use crate::render;
+use crate::status::Problem;
+use crate::surface::{StatusSurface, Stopper};
+use crate::worker::{Refresh, RefreshRequest};
+use crate::NSCallback;
+
+enum Event {
+    Stop,
+    Activate(usize),
+    RefreshProject(String),
+}
+
+/// State shared with the D-Bus service thread.  Menu activations are sent
+/// back as events so the callbacks run on the thread that owns them; clicking
+/// a project refreshes just that project.
+struct StatusTray {
+    title: String,
+    /// Menu label and `Project::name` of each project.
+    projects: Vec<(String, String)>,
+    icon: &'static str,
+    /// Shown above the projects in the tooltip.
+    problems: Vec<String>,
+    /// Shown as a disabled item at the top of the menu.
+    config_error: Option<String>,
+    throttled: bool,
+    items: Vec<String>,
+    events: Sender<Event>,
+}
+
+impl Tray for StatusTray {
+    const MENU_ON_ACTIVATE: bool = true;
+
+    fn id(&self) -> String {
+        env!("CARGO_PKG_NAME").into()
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn icon_name(&self) -> String {
+        self.icon.into()
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: self.title.clone(),
+            description: self
+                .problems
+                .iter()
+                .map(String::as_str)
+                .chain(self.projects.iter().map(|(label, _)| label.as_str()))
+                .chain(if self.throttled { Some(render::THROTTLED) } else { None })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ..Default::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let mut menu: Vec<MenuItem<Self>> = Vec::new();
+        if let Some(e) = &self.config_error {
+            menu.push(
+                StandardItem {
+                    label: format!("Config not reloaded: {:}", e),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+            menu.push(MenuItem::Separator);
+        }
+        menu.extend(self.projects.iter().map(|(label, name)| {
+            let name = name.clone();
+            StandardItem {
+                label: label.clone(),
+                activate: Box::new(move |tray: &mut Self| {
+                    let _ = tray.events.send(Event::RefreshProject(name.clone()));
+                }),
+                ..Default::default()
+            }
+            .into()
+        }));
+        if !self.projects.is_empty() {
+            menu.push(MenuItem::Separator);
+        }
+        menu.extend(self.items.iter().enumerate().map(|(i, label)| {
+            StandardItem {
+                label: label.clone(),
+                activate: Box::new(move |tray: &mut Self| {
+                    let _ = tray.events.send(Event::Activate(i));
+                }),
+                ..Default::default()
+            }
+            .into()
+        }));
+        menu
+    }
+}
+
+/// Linux system tray icon using the freedesktop StatusNotifierItem protocol.
+pub struct TrayStatus {
+    handle: Handle<StatusTray>,
+    tx: Sender<RefreshRequest>,
+    callbacks: Vec<NSCallback>,
+    events: Receiver<Event>,
+    event_tx: Sender<Event>,
+}
+
+pub struct TrayStopper {
+    tx: Sender<Event>,
+}
+
+impl Stopper for TrayStopper {
+    fn stop(&self) {
+        let _ = self.tx.send(Event::Stop);
+    }
+}
+
+impl TrayStatus {
+    /// Registers the tray on the session bus.  Fails when there is no bus or
+    /// no StatusNotifierWatcher to register with.
+    pub fn new(title: &str, tx: Sender<RefreshRequest>) -> Result<TrayStatus, ksni::Error> {
+        let (event_tx, events) = channel();
+        let handle = StatusTray {
+            title: title.to_string(),
+            projects: Vec::new(),
+            icon: icon(None, &[]),
+            problems: Vec::new(),
+            config_error: None,
+            throttled: false,
+            items: Vec::new(),
+            events: event_tx.clone(),
+        }
+        .spawn()?;
+
+        Ok(TrayStatus {
+            handle,
+            tx,
+            callbacks: Vec::new(),
+            events,
+            event_tx,
+        })
+    }
+
+    fn handle(&self, event: Event) -> bool {
+        match event {
+            Event::Stop => true,
+            Event::Activate(i) => {
+                if let Some(cb) = self.callbacks.get(i) {
+                    cb(i as u64, &self.tx);
+                }
+                false
+            }
+            Event::RefreshProject(name) => {
+                let _ = self.tx.send(RefreshRequest::Project(name));
+                false
+            }
+        }
+    }
+}
+
+fn icon(worst: Option<PipelineStatus>, problems: &[Problem]) -> &'static str {
+    if problems.iter().any(Problem::is_token) {
+        return "dialog-password";
+    }
+    if problems.contains(&Problem::Offline) {
+        return "network-offline";
+    }
+    match worst {
+        Some(PipelineStatus::Failed) => "dialog-error",
+        Some(PipelineStatus::Running) | Some(PipelineStatus::Pending) => "view-refresh",
+        Some(PipelineStatus::Canceled) => "dialog-warning",
+        Some(_) => "emblem-default",
+        None => "dialog-question",
+    }
+}
+
+impl StatusSurface for TrayStatus {
+    fn set_title(&self, title: &str) {
+        let title = title.to_string();
+        self.handle.update(move |tray| tray.title = title);
+    }
+
+    fn update(&self, refresh: &Refresh) {
+        let title = refresh.title.clone();
+        let projects = refresh
+            .projects
+            .iter()
+            .map(|p| (render::tooltip_line(p), p.name.clone()))
+            .collect();
+        let problems = refresh.problems();
+        let icon = icon(refresh.worst(), &problems);
+        let problems = problems.iter().map(Problem::to_string).collect();
+        let throttled = refresh.throttled;
+        let config_error = refresh.config_error.clone();
+        self.handle.update(move |tray| {
+            tray.title = title;
+            tray.projects = projects;
+            tray.icon = icon;
+            tray.problems = problems;
+            tray.config_error = config_error;
+            tray.throttled = throttled;
+        });
+    }
+
+    fn add_item(&mut self, item: &str, callback: NSCallback, _selected: bool) {
+        self.callbacks.push(callback);
+        let item = item.to_string();
+        self.handle.update(move |tray| tray.items.push(item));
+    }
+
+    fn run(&mut self, block: bool) {
+        if block {
+            while let Ok(event) = self.events.recv() {
+                if self.handle(event) {
+                    break;
+                }
+            }
+        } else {
+            while let Ok(event) = self.events.try_recv() {
+                if self.handle(event) {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn stopper(&self) -> Box<dyn Stopper> {
+        Box::new(TrayStopper {
+            tx: self.event_tx.clone(),
+        })
+    }
+}
@@ -0,0 +1,115 @@
+extern crate serde_json;
+
+use std::sync::mpsc::Sender;
+
+use serde::Serialize;
+
+use crate::gitlab::PipelineStatus;
+use crate::render;
+use crate::status::Problem;
+use crate::surface::{StatusSurface, Stopper};
+use crate::terminal::TerminalStatus;
+use crate::worker::{Refresh, RefreshRequest};
+use crate::NSCallback;
+
+/// One line of the Waybar custom module / i3blocks JSON protocol.
+#[derive(Serialize, Debug)]
+struct Block<'a> {
+    text: &'a str,
+    /// i3blocks reads `full_text` rather than `text`.
+    full_text: &'a str,
+    tooltip: &'a str,
+    /// Waybar accepts a list of classes; `stale` is added next to the status.
+    class: Vec<&'a str>,
+    percentage: u8,
+}
+
+/// Status surface printing one JSON object per refresh, for Waybar, i3blocks
+/// and polybar.  Menu items still work through stdin like `TerminalStatus`.
+pub struct WaybarStatus {
+    terminal: TerminalStatus,
+}
+
+impl WaybarStatus {
+    pub fn new(title: &str, tx: Sender<RefreshRequest>) -> WaybarStatus {
+        let status = WaybarStatus {
+            terminal: TerminalStatus::listen(tx),
+        };
+        status.set_title(title);
+        status
+    }
+
+    fn print(&self, block: &Block) {
+        match serde_json::to_string(block) {
+            Ok(line) => println!("{:}", line),
+            Err(e) => eprintln!("error encoding status: {:?}", e),
+        }
+    }
+}
+
+/// CSS class for the block: bars style `failed` red and `running`/`pending`
+/// yellow.  `unauthorized`, for a rejected or unreadable token, and `offline`
+/// take precedence over any status.
+fn class(worst: Option<PipelineStatus>, problems: &[Problem]) -> &'static str {
+    if problems.iter().any(Problem::is_token) {
+        return "unauthorized";
+    }
+    if problems.contains(&Problem::Offline) {
+        return "offline";
+    }
+    match worst {
+        Some(PipelineStatus::Failed) => "failed",
+        Some(PipelineStatus::Running) => "running",
+        Some(PipelineStatus::Pending) => "pending",
+        Some(PipelineStatus::Canceled) => "canceled",
+        Some(PipelineStatus::Skipped) => "skipped",
+        Some(PipelineStatus::Manual) => "manual",
+        Some(PipelineStatus::Success) => "success",
+        None => "unknown",
+    }
+}
+
+impl StatusSurface for WaybarStatus {
+    fn set_title(&self, title: &str) {
+        self.print(&Block {
+            text: title,
+            full_text: title,
+            tooltip: "",
+            class: vec![class(None, &[])],
+            percentage: 0,
+        });
+    }
+
+    fn update(&self, refresh: &Refresh) {
+        let mut tooltip = render::tooltip(&refresh.projects);
+        if let Some(e) = &refresh.config_error {
+            tooltip = format!("config not reloaded: {:}\n{:}", e, tooltip);
+        }
+        if refresh.throttled {
+            tooltip.push_str(&format!("\n{:}", render::THROTTLED));
+        }
+        let mut classes = vec![class(refresh.worst(), &refresh.problems())];
+        if refresh.is_stale() {
+            classes.push("stale");
+        }
+        self.print(&Block {
+            text: &refresh.title,
+            full_text: &refresh.title,
+            tooltip: &tooltip,
+            class: classes,
+            percentage: refresh.percentage(),
+        });
+    }
+
+    fn add_item(&mut self, item: &str, callback: NSCallback, selected: bool) {
+        self.terminal.add_item(item, callback, selected);
+    }
+
+    fn run(&mut self, block: bool) {
+        self.terminal.run(block);
+    }
+
+    fn stopper(&self) -> Box<dyn Stopper> {
+        self.terminal.stopper()
+    }
+}
@@ -0,0 +1,201 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::{read_config, Config};
+use crate::gitlab::{Gitlab, PipelineStatus};
+use crate::render::make_title;
+use crate::state;
+use crate::status::{fetch, keep_last_good, problems, Problem, ProjectStatus};
+use crate::surface::Stopper;
+
+/// Why the worker should refresh, sent by the menu, timers and frontends.
+#[derive(Clone, Debug)]
+pub enum RefreshRequest {
+    Manual,
+    /// Sent by the worker to itself every `interval`.
+    Interval,
+    /// Refresh only the project with this `Project::name`.
+    Project(String),
+    /// Re-read the config file, then refresh everything.
+    ConfigReloaded,
+    Shutdown,
+}
+
+/// Result of one refresh of every configured project.
+#[derive(Serialize, Clone, Debug)]
+pub struct Refresh {
+    pub title: String,
+    pub projects: Vec<ProjectStatus>,
+    /// GitLab rate limited us during the refresh, so it may have been slow.
+    pub throttled: bool,
+    /// Why the config file could not be reloaded; the previous config is
+    /// still in use.
+    pub config_error: Option<String>,
+}
+
+impl Refresh {
+    /// Most severe pipeline status on any monitored ref of any project.
+    pub fn worst(&self) -> Option<PipelineStatus> {
+        self.projects
+            .iter()
+            .filter_map(|p| p.pipeline.as_ref().ok())
+            .copied()
+            .max_by_key(|s| s.severity())
+    }
+
+    /// Whether any project shows its last known status instead of a fresh one.
+    pub fn is_stale(&self) -> bool {
+        self.projects.iter().any(ProjectStatus::is_stale)
+    }
+
+    pub fn problems(&self) -> Vec<Problem> {
+        problems(&self.projects)
+    }
+
+    /// Share of projects whose monitored refs are all passing.
+    pub fn percentage(&self) -> u8 {
+        if self.projects.is_empty() {
+            return 100;
+        }
+        let passing = self
+            .projects
+            .iter()
+            .filter(|p| matches!(p.pipeline, Ok(PipelineStatus::Success)))
+            .count();
+        (passing * 100 / self.projects.len()) as u8
+    }
+}
+
+/// Serves refresh requests until `Shutdown` or until either channel closes,
+/// and refreshes everything every `interval`, starting right away.  The
+/// interval is taken from the config again when it is reloaded.
+/// A project whose refresh fails keeps its last good status, marked stale.
+/// Statuses saved by the previous run are sent before the first refresh.
+/// A config that fails to reload is reported and the old one kept.
+/// `stopper` is triggered after every result so a blocked surface wakes up.
+pub fn run(
+    mut config: Config,
+    requests: Receiver<RefreshRequest>,
+    results: Sender<Refresh>,
+    stopper: Option<Box<dyn Stopper>>,
+) {
+    let mut projects = state::load(&config);
+    let mut config_error: Option<String> = None;
+    if !projects.is_empty() && !publish(&config, &projects, false, &config_error, &results, &stopper) {
+        return;
+    }
+    let mut gl = client(&config);
+
+    let mut next_refresh = Instant::now();
+    loop {
+        let request = match requests.recv_timeout(next_refresh.saturating_duration_since(Instant::now())) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout) => RefreshRequest::Interval,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        progress!("refreshing: {:?}", request);
+        match &request {
+            RefreshRequest::Shutdown => break,
+            RefreshRequest::ConfigReloaded => match read_config(Some(&config.path)) {
+                Ok(reloaded) => {
+                    config = reloaded;
+                    gl = client(&config);
+                    config_error = None;
+                    refresh_all(&gl, &config, &mut projects);
+                    next_refresh = Instant::now() + Duration::from_secs(config.interval);
+                }
+                Err(e) => {
+                    error!("error reloading config: {:}", e);
+                    config_error = Some(e.to_string());
+                }
+            },
+            RefreshRequest::Project(name) if projects.len() == config.project.len() => {
+                if let Some(i) = config.project.iter().position(|p| p.name == *name) {
+                    projects[i] = keep_last_good(fetch(&gl, &config.project[i]), Some(&projects[i]));
+                }
+            }
+            _ => {
+                refresh_all(&gl, &config, &mut projects);
+                next_refresh = Instant::now() + Duration::from_secs(config.interval);
+            }
+        }
+
+        if let Err(e) = state::save(&projects) {
+            error!("error saving state: {:}", e);
+        }
+        if !publish(&config, &projects, gl.take_throttled(), &config_error, &results, &stopper) {
+            break;
+        }
+    }
+    // hang up before waking the surface so it sees the disconnect
+    drop(results);
+    if let Some(stopper) = &stopper {
+        stopper.stop();
+    }
+}
+
+pub fn client(config: &Config) -> Gitlab {
+    let token = config.token_source().expect("token source checked by Config::validate");
+    Gitlab::new(&config.gitlab_url, token, config.parallelism)
+}
+
+/// Fetches every configured project, keeping last good statuses by name so
+/// they survive projects being added or reordered.
+fn refresh_all(gl: &Gitlab, config: &Config, projects: &mut Vec<ProjectStatus>) {
+    let fresh = gl.threads().map(&config.project, |p| fetch(gl, p));
+    *projects = fresh
+        .into_iter()
+        .map(|p| {
+            let previous = projects.iter().find(|q| q.name == p.name);
+            keep_last_good(p, previous)
+        })
+        .collect();
+}
+
+/// Sends `ConfigReloaded` whenever the modification time of the config file
+/// at `path` changes.  Returns once the worker has gone away.
+pub fn watch_config(path: PathBuf, tx: Sender<RefreshRequest>) {
+    let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last = modified(&path);
+    loop {
+        thread::sleep(Duration::from_secs(2));
+        let current = modified(&path);
+        // a missing file is usually an editor halfway through saving
+        if current.is_some() && current != last {
+            last = current;
+            if tx.send(RefreshRequest::ConfigReloaded).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Sends the statuses to the surface and wakes it.  False once nobody is
+/// listening any more.
+fn publish(
+    config: &Config,
+    projects: &[ProjectStatus],
+    throttled: bool,
+    config_error: &Option<String>,
+    results: &Sender<Refresh>,
+    stopper: &Option<Box<dyn Stopper>>,
+) -> bool {
+    let refresh = Refresh {
+        title: make_title(config, projects),
+        projects: projects.to_vec(),
+        throttled,
+        config_error: config_error.clone(),
+    };
+    if results.send(refresh).is_err() {
+        return false;
+    }
+    if let Some(stopper) = stopper {
+        stopper.stop();
+    }
+    true
+}
@@ -0,0 +1,77 @@
+//! Runs `check-config` against a stub GitLab serving the endpoints it uses.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{start_gitlab, write_config, TOKEN};
+
+/// Writes a config for `project` pointing at a fresh stub and runs
+/// `check-config` on it, returning the exit code and the report.
+fn check(name: &str, token: &str, project: &str) -> (i32, String) {
+    let config = format!(
+        "gitlab_url = \"{:}\"\ntoken = \"{:}\"\n\n[[project]]\n{:}\n",
+        start_gitlab(),
+        token,
+        project
+    );
+    let path = write_config(&format!("check-{:}", name), &config);
+    let output = Command::new(env!("CARGO_BIN_EXE_cl-worktool"))
+        .arg("check-config")
+        .arg("--config")
+        .arg(&path)
+        .output()
+        .expect("run check-config");
+    let _ = fs::remove_file(&path);
+    (
+        output.status.code().expect("exit code"),
+        String::from_utf8(output.stdout).expect("utf-8 report"),
+    )
+}
+
+#[test]
+fn good_config_passes() {
+    let (code, report) = check("good", TOKEN, "name = \"g/a\"\nref = \"main\"\nrefs = [\"v1\", \"ma*\"]");
+    assert_eq!(code, 0, "{:}", report);
+    assert!(report.contains("ok: token belongs to @me"), "{:}", report);
+    assert!(report.contains("ok: token \"worktool\" has scopes read_api"), "{:}", report);
+    assert!(report.contains("ok: project g/a found"), "{:}", report);
+    assert!(report.contains("ok: project g/a: ref main found"), "{:}", report);
+    assert!(report.contains("ok: project g/a: ref v1 found"), "{:}", report);
+    assert!(report.contains("ok: project g/a: 1 branches match ma*"), "{:}", report);
+    assert!(report.contains("0 error(s), 0 warning(s)"), "{:}", report);
+}
+
+#[test]
+fn bad_token_fails() {
+    let (code, report) = check("token", "revoked", "name = \"g/a\"");
+    assert_eq!(code, 1, "{:}", report);
+    assert!(report.contains("error: token rejected (401) by /api/v4/user"), "{:}", report);
+    assert!(!report.contains("project g/a"), "{:}", report);
+}
+
+#[test]
+fn unknown_project_fails() {
+    let (code, report) = check("project", TOKEN, "name = \"g/missing\"");
+    assert_eq!(code, 1, "{:}", report);
+    assert!(
+        report.contains("error: project g/missing: not found (404): /api/v4/projects/g%2Fmissing"),
+        "{:}",
+        report
+    );
+}
+
+#[test]
+fn missing_ref_fails() {
+    let (code, report) = check("ref", TOKEN, "name = \"g/a\"\nrefs = [\"nope\"]");
+    assert_eq!(code, 1, "{:}", report);
+    assert!(report.contains("error: project g/a: no branch or tag named nope"), "{:}", report);
+}
+
+#[test]
+fn unknown_key_fails() {
+    let (code, report) = check("key", TOKEN, "name = \"g/a\"\nbranch_user = [\"me\"]");
+    assert_eq!(code, 1, "{:}", report);
+    assert!(report.contains("error: line 6: unknown key project.branch_user"), "{:}", report);
+}
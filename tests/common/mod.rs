@@ -0,0 +1,118 @@
+//! A stub GitLab for the integration tests, serving a few endpoints of one
+//! project.
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+
+pub const TOKEN: &str = "good-token";
+
+const BRANCH: &str = r#"{"name": "main", "merged": false, "commit": {"id": "abc", "message": "m",
+    "author_name": "a", "author_email": "a@x", "committer_name": "a", "committer_email": "a@x",
+    "created_at": "2020-01-01T00:00:00Z"}}"#;
+
+const PIPELINES: &str = r#"[{"id": 2, "status": "failed", "ref": "main", "sha": "b"},
+    {"id": 1, "status": "success", "ref": "main", "sha": "a"}]"#;
+
+const FAILED_PIPELINE: &str = r#"{"id": 3, "status": "failed", "ref": "feature/x", "sha": "abc"}"#;
+
+const MERGE_REQUESTS: &str = r#"[{"id": 1, "iid": 1, "title": "t", "author": {"id": 1, "username": "bot"},
+    "source_branch": "feature/x", "sha": "abc", "updated_at": "2020-01-01T00:00:00Z", "web_url": "http://x/1"},
+    {"id": 2, "iid": 2, "title": "t", "author": {"id": 1, "username": "bot"},
+    "source_branch": "gone", "sha": "abc", "updated_at": "2020-01-01T00:00:00Z", "web_url": "http://x/2"}]"#;
+
+/// Answers one request: 401 without the right token, otherwise the
+/// project `g/a` with branch `main`, tag `v1` and any pattern matching `main`,
+/// whose newest pipeline failed.  `g/b` has the same pipelines but the
+/// newest one's details cannot be fetched.  `g/c` has a failed pipeline on
+/// the merge request from `feature/x`, whose branch `g/d` cannot look up.
+fn respond(path: &str, token: Option<&str>) -> (u16, String) {
+    if token != Some(TOKEN) {
+        return (401, r#"{"message": "401 Unauthorized"}"#.to_string());
+    }
+    let body = match path {
+        "/api/v4/user" => r#"{"username": "me"}"#.to_string(),
+        "/api/v4/personal_access_tokens/self" => {
+            r#"{"name": "worktool", "scopes": ["read_api"], "expires_at": null}"#.to_string()
+        }
+        "/api/v4/projects/g%2Fa" => r#"{"id": 1, "default_branch": "main"}"#.to_string(),
+        "/api/v4/projects/g%2Fa/repository/branches?per_page=100" => format!("[{:}]", BRANCH),
+        "/api/v4/projects/g%2Fa/repository/branches/main" => BRANCH.to_string(),
+        "/api/v4/projects/g%2Fa/repository/tags/v1" => r#"{"name": "v1"}"#.to_string(),
+        "/api/v4/projects/g%2Fa/merge_requests?state=opened&per_page=100" => "[]".to_string(),
+        "/api/v4/projects/g%2Fa/pipelines?ref=main&per_page=100" => PIPELINES.to_string(),
+        "/api/v4/projects/g%2Fa/pipelines/2" => r#"{"id": 2, "status": "failed", "before_sha": "a"}"#.to_string(),
+        "/api/v4/projects/g%2Fb" => r#"{"id": 2, "default_branch": "main"}"#.to_string(),
+        "/api/v4/projects/g%2Fb/merge_requests?state=opened&per_page=100" => "[]".to_string(),
+        "/api/v4/projects/g%2Fb/pipelines?ref=main&per_page=100" => PIPELINES.to_string(),
+        "/api/v4/projects/g%2Fb/pipelines/1" => r#"{"id": 1, "status": "success", "before_sha": "c"}"#.to_string(),
+        "/api/v4/projects/g%2Fc" | "/api/v4/projects/g%2Fd" => r#"{"id": 3, "default_branch": "main"}"#.to_string(),
+        "/api/v4/projects/g%2Fc/pipelines?ref=main&per_page=100"
+        | "/api/v4/projects/g%2Fd/pipelines?ref=main&per_page=100" => "[]".to_string(),
+        "/api/v4/projects/g%2Fc/merge_requests?state=opened&per_page=100"
+        | "/api/v4/projects/g%2Fd/merge_requests?state=opened&per_page=100" => MERGE_REQUESTS.to_string(),
+        "/api/v4/projects/g%2Fc/repository/branches/feature%2Fx" => BRANCH.replace("main", "feature/x"),
+        "/api/v4/projects/g%2Fc/pipelines?status=failed&sha=abc&per_page=1" => format!("[{:}]", FAILED_PIPELINE),
+        "/api/v4/projects/g%2Fd/repository/branches/feature%2Fx" => {
+            return (403, r#"{"message": "403 Forbidden"}"#.to_string())
+        }
+        _ => return (404, r#"{"message": "404 Not Found"}"#.to_string()),
+    };
+    (200, body)
+}
+
+fn serve(mut stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("").to_string();
+    let mut token = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("private-token") {
+                token = Some(value.trim().to_string());
+            }
+        }
+    }
+    let (status, body) = respond(&path, token.as_deref());
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {:} X\r\nContent-Type: application/json\r\nContent-Length: {:}\r\nConnection: close\r\n\r\n{:}",
+        status,
+        body.len(),
+        body
+    );
+}
+
+/// Starts the stub on a free port and returns its URL.
+pub fn start_gitlab() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind stub");
+    let url = format!("http://{:}", listener.local_addr().expect("stub address"));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || serve(stream));
+        }
+    });
+    url
+}
+
+/// A path in the temp directory unique to this test run.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("cl-worktool-{:}-{:}", name, std::process::id()))
+}
+
+/// Writes `config` to a temp file and returns its path.
+pub fn write_config(name: &str, config: &str) -> PathBuf {
+    let path = temp_path(&format!("{:}.toml", name));
+    fs::write(&path, config).expect("write config");
+    path
+}
@@ -0,0 +1,68 @@
+//! Runs `watch` against a stub GitLab and rewrites its config file while it
+//! runs, to see the worker keep or replace the config.
+
+mod common;
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use common::{start_gitlab, temp_path, write_config, TOKEN};
+
+#[test]
+fn invalid_rewrite_keeps_the_old_config_until_fixed() {
+    let config = format!(
+        "gitlab_url = \"{:}\"\ntoken = \"{:}\"\ninterval = 3600\n\n[[project]]\nname = \"g/a\"\n",
+        start_gitlab(),
+        TOKEN
+    );
+    let path = write_config("reload", &config);
+    // keeps the state file out of the real data directory
+    let home = temp_path("reload-home");
+    fs::create_dir_all(&home).expect("create home");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cl-worktool"))
+        .args(["watch", "--quiet", "--format", "json", "--config"])
+        .arg(&path)
+        .env("HOME", &home)
+        .env("XDG_DATA_HOME", &home)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("run watch");
+
+    let (tx, rx) = channel();
+    let stdout = child.stdout.take().expect("watch stdout");
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let refresh: serde_json::Value = serde_json::from_str(&line).expect("refresh as JSON");
+            if tx.send(refresh).is_err() {
+                return;
+            }
+        }
+    });
+    let next = || rx.recv_timeout(Duration::from_secs(20)).expect("a refresh");
+
+    let first = next();
+    assert_eq!(first["config_error"], serde_json::Value::Null);
+    assert_eq!(first["projects"][0]["name"], "g/a");
+
+    fs::write(&path, config.replace("[[project]]", "[[project]")).expect("break config");
+    let broken = next();
+    let error = broken["config_error"].as_str().unwrap_or_default().to_string();
+    assert!(error.contains("error in"), "{:}", broken);
+    // the old config is still in use
+    assert_eq!(broken["projects"][0]["name"], "g/a");
+
+    fs::write(&path, config.replace("g/a", "g/b")).expect("fix config");
+    let fixed = next();
+    assert_eq!(fixed["config_error"], serde_json::Value::Null);
+    assert_eq!(fixed["projects"][0]["name"], "g/b");
+
+    let _ = child.kill();
+    let _ = child.wait();
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir_all(&home);
+}
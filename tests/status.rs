@@ -0,0 +1,63 @@
+//! Runs `status` against a stub GitLab.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{start_gitlab, write_config, TOKEN};
+
+/// Runs `status` on a fresh stub with one `[[project]]` table, returning the
+/// exit code and the report.
+fn status(name: &str, project: &str) -> (i32, String) {
+    let config = format!(
+        "gitlab_url = \"{:}\"\ntoken = \"{:}\"\n\n[[project]]\n{:}\n",
+        start_gitlab(),
+        TOKEN,
+        project
+    );
+    let path = write_config(&format!("status-{:}", name), &config);
+    let output = Command::new(env!("CARGO_BIN_EXE_cl-worktool"))
+        .args(["status", "--quiet", "--config"])
+        .arg(&path)
+        .output()
+        .expect("run status");
+    let _ = fs::remove_file(&path);
+    (
+        output.status.code().expect("exit code"),
+        String::from_utf8(output.stdout).expect("utf-8 report"),
+    )
+}
+
+#[test]
+fn newest_pushed_pipeline_is_reported() {
+    let (code, report) = status("failed", "name = \"g/a\"");
+    assert_eq!(code, 2, "{:}", report);
+    assert!(report.contains("Failed on main"), "{:}", report);
+}
+
+#[test]
+fn unfetchable_pipeline_detail_is_an_error() {
+    // the older pipeline must not stand in for one whose details are missing
+    let (code, report) = status("detail", "name = \"g/b\"");
+    assert_eq!(code, 1, "{:}", report);
+    assert!(!report.contains("Success"), "{:}", report);
+}
+
+#[test]
+fn failed_branch_of_ours_is_listed() {
+    let (_, report) = status(
+        "failing",
+        "name = \"g/c\"\nignore_users = [\"bot\"]\nbranch_users = [\"a\"]",
+    );
+    assert!(report.contains("0 to review, failing: feature/x"), "{:}", report);
+}
+
+#[test]
+fn branch_lookup_error_is_not_an_empty_list() {
+    let (_, report) = status(
+        "branch",
+        "name = \"g/d\"\nignore_users = [\"bot\"]\nbranch_users = [\"a\"]",
+    );
+    assert!(report.contains("failing: unknown"), "{:}", report);
+}
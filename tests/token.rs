@@ -0,0 +1,54 @@
+//! Runs `status` with a `token_command` that counts how often it runs.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{start_gitlab, temp_path, write_config};
+
+/// Runs `status` on a project of the stub with `token_command` set to
+/// `command` after appending a line to a counter file.  Returns the exit
+/// code, stdout and how often the command ran.
+fn status(name: &str, command: &str) -> (i32, String, usize) {
+    let count = temp_path(&format!("{:}-count", name));
+    let _ = fs::remove_file(&count);
+    let config = format!(
+        "gitlab_url = \"{:}\"\ntoken_command = \"echo >> '{:}'; {:}\"\n\n[[project]]\nname = \"g/a\"\n",
+        start_gitlab(),
+        count.display(),
+        command
+    );
+    let path = write_config(name, &config);
+    let output = Command::new(env!("CARGO_BIN_EXE_cl-worktool"))
+        .args(["status", "--quiet", "--config"])
+        .arg(&path)
+        .output()
+        .expect("run status");
+    let runs = fs::read_to_string(&count).map(|c| c.lines().count()).unwrap_or(0);
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&count);
+    (
+        output.status.code().expect("exit code"),
+        String::from_utf8(output.stdout).expect("utf-8 output"),
+        runs,
+    )
+}
+
+#[test]
+fn revoked_token_is_read_again_once() {
+    let (code, output, runs) = status("token-revoked", "echo revoked");
+    assert_eq!(code, 1, "{:}", output);
+    assert!(output.contains("GitLab rejected the token"), "{:}", output);
+    // the first read, then one more after the first 401
+    assert_eq!(runs, 2);
+}
+
+#[test]
+fn failing_command_is_not_a_rejected_token() {
+    let (code, output, runs) = status("token-failing", "exit 3");
+    assert_eq!(code, 1, "{:}", output);
+    assert!(output.contains("cannot read token: token_command"), "{:}", output);
+    assert!(!output.contains("GitLab rejected the token"), "{:}", output);
+    assert_eq!(runs, 1);
+}